@@ -5,10 +5,12 @@ use rusqlite::{Connection, Result, ToSql};
 use rusqlite::types::{ToSqlOutput, FromSql};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
-use alloy::primitives::{Address, keccak256};
+use alloy::primitives::{Address, B256, PrimitiveSignature, U256, keccak256};
+use alloy::sol_types::{Eip712Domain, SolCall, SolEvent, SolInterface, SolStruct, SolValue};
 use derive_more::{From, Display, FromStr};
 use rusqlite::Row;
 use rusqlite::named_params;
+use rusqlite::OptionalExtension;
 use std::convert::TryFrom;
 
 #[derive(Debug, Clone, Copy, From, Display, FromStr, PartialEq)]
@@ -37,6 +39,33 @@ impl rusqlite::types::FromSql for AddressSqlite {
     }
 }
 
+// Token ids and amounts are stored as 32-byte big-endian blobs. SQLite
+// compares blobs with memcmp, so ordering and range comparisons on these
+// columns match numeric ordering
+#[derive(Debug, Clone, Copy, From, Display, FromStr, PartialEq, PartialOrd)]
+#[display("{}", _0)]
+struct U256Sqlite(U256);
+
+impl ToSql for U256Sqlite {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.to_be_bytes::<32>().to_vec()))
+    }
+}
+
+impl rusqlite::types::FromSql for U256Sqlite {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value {
+            rusqlite::types::ValueRef::Blob(bytes) => {
+                if bytes.len() != 32 {
+                    return Err(rusqlite::types::FromSqlError::InvalidType);
+                }
+                Ok(U256Sqlite(U256::from_be_slice(bytes)))
+            }
+            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Transactions {
     id: i32,
@@ -44,13 +73,26 @@ struct Transactions {
     transaction_type: TransactionType,
     data: Vec<u8>,
     timestamp: i64,
+    // Optional EIP-712 signature by the sender over the transaction, see
+    // transaction_signing_hash
+    signature: Option<Vec<u8>>,
+    // Set when the transaction was submitted by a relayer on the sender's
+    // behalf, see insert_meta_transaction
+    relayer: Option<AddressSqlite>,
+    // Signature by the relayer over the same hash the sender signed
+    relayer_signature: Option<Vec<u8>>,
+    // The block the transaction was included in. Like `id`, this is assigned
+    // by insert_transaction and ignored on input
+    block_number: Option<i64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, strum::Display, strum::EnumString, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, strum::Display, strum::EnumString, PartialEq)]
 enum TransactionType {
     CreateToken,
     AddTokenSigner,
     RemoveTokenSigner,
+    GrantRole,
+    RevokeRole,
     SetDefaultTokenURI,
     SetTokenURIPerId,
     Mint,
@@ -58,6 +100,40 @@ enum TransactionType {
     Burn,
     Approve,
     SetApprovalForAll,
+    SetApprovalThreshold,
+    ProposeOperation,
+    ApproveProposal,
+    ExecuteProposal,
+    DelegateSessionKey,
+    RevokeSessionKey,
+    AcceptSessionKey,
+    Pause,
+    Unpause,
+    TransferOwnership,
+    AcceptOwnership,
+    SetDefaultRoyalty,
+    SetTokenRoyalty,
+    SetContractURI,
+    SetContractMetadata,
+    FreezeMetadata,
+    MarkSoulbound,
+    MintRange,
+    MintNext,
+    RedeemVoucher,
+    SetRelayer,
+    SetMintPhase,
+    ClaimAllowlist,
+    PublicMint,
+    SetAllowance,
+    TransferFrom,
+    Permit,
+    ForceTransfer,
+    DisableClawback,
+    SetBlocked,
+    SetAllowedRecipient,
+    SetRecipientAllowlist,
+    SetUser,
+    SetTokenAttributes,
 }
 
 impl ToSql for TransactionType {
@@ -74,6 +150,53 @@ impl FromSql for TransactionType {
     }
 }
 
+// Roles a signer can hold on a contract. Admins manage signers and roles,
+// minters mint, URI managers set token URIs and clawback operators move
+// tokens out of accounts without the holder's approval
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, strum::Display, strum::EnumString, PartialEq)]
+enum Role {
+    Admin,
+    Minter,
+    UriManager,
+    Clawback,
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        let text = value.as_str()?;
+        text.parse()
+            .map_err(|_| rusqlite::types::FromSqlError::InvalidType)
+    }
+}
+
+// Phases of a drop in which end users mint for themselves. Allowlist mints
+// need a Merkle proof, public mints are open to anyone
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, strum::Display, strum::EnumString, PartialEq)]
+enum MintPhase {
+    Allowlist,
+    Public,
+}
+
+impl ToSql for MintPhase {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.to_string()))
+    }
+}
+
+impl FromSql for MintPhase {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        let text = value.as_str()?;
+        text.parse()
+            .map_err(|_| rusqlite::types::FromSqlError::InvalidType)
+    }
+}
+
 struct AddressSqliteList(Vec<AddressSqlite>);
 
 // Show AddressSqliteList as a comma-separated list of addresses
@@ -121,12 +244,76 @@ impl FromSql for AddressSqliteList {
     }
 }
 
+// SQL aggregate that concatenates 20-byte signer addresses into the blob
+// format read by AddressSqliteList. Used by the contracts_with_signers view so
+// that Contracts keeps exposing the full signer list
+struct AddressListAggregate;
+
+impl rusqlite::functions::Aggregate<Vec<u8>, Vec<u8>> for AddressListAggregate {
+    fn init(&self, _ctx: &mut rusqlite::functions::Context<'_>) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn step(&self, ctx: &mut rusqlite::functions::Context<'_>, acc: &mut Vec<u8>) -> Result<()> {
+        let address: AddressSqlite = ctx.get(0)?;
+        acc.extend_from_slice(address.0.as_slice());
+        Ok(())
+    }
+
+    fn finalize(&self, _ctx: &mut rusqlite::functions::Context<'_>, acc: Option<Vec<u8>>) -> Result<Vec<u8>> {
+        // A contract without signers yields an empty list rather than NULL
+        Ok(acc.unwrap_or_default())
+    }
+}
+
 #[derive(Debug)]
 struct Contracts {
     id: i32,
     address: AddressSqlite,
     signers: AddressSqliteList,
     transaction_id: i32,
+    default_token_uri: Option<String>,
+    // Number of signer approvals required for sensitive operations. A
+    // threshold of 1 lets a single signer with the right role act directly
+    approval_threshold: i64,
+    // Caps on the total amount ever minted and on the amount minted to any
+    // one recipient. None when uncapped
+    max_supply: Option<U256Sqlite>,
+    max_per_address: Option<U256Sqlite>,
+    // Burns do not lower this, so a capped supply cannot be minted again
+    // after burning
+    total_minted: U256Sqlite,
+    // Paused contracts reject mints, transfers, burns and approvals
+    paused: bool,
+    // The sender of CreateToken owns the contract until ownership is handed
+    // over with TransferOwnership and AcceptOwnership
+    owner: AddressSqlite,
+    pending_owner: Option<AddressSqlite>,
+    // ERC-2981 royalty applied to tokens without their own royalty
+    royalty_receiver: Option<AddressSqlite>,
+    royalty_bps: Option<u16>,
+    // Collection-level metadata. contract_uri is served as contractURI()
+    name: String,
+    symbol: String,
+    description: String,
+    contract_uri: Option<String>,
+    // Once set, no token URI on the contract can change again
+    metadata_frozen: bool,
+    // Tokens of a soulbound contract can be minted and burned but never
+    // transferred or approved. Individual ids can be marked with MarkSoulbound
+    soulbound: bool,
+    // Lowest id that MintNext may allocate. Ids at or above it that were
+    // minted explicitly are skipped
+    next_token_id: U256Sqlite,
+    // Once set, ForceTransfer is rejected for good, even for holders of the
+    // Clawback role
+    clawback_disabled: bool,
+    // When set, tokens can only be minted or transferred to addresses on the
+    // contract's recipient allowlist
+    recipient_allowlist: bool,
+    // The name the contract was created with, which names its EIP-2612
+    // domain. Renaming the collection keeps it, so signed permits stay valid
+    permit_name: String,
 }
 
 impl TryFrom<&Row<'_>> for Contracts {
@@ -138,14 +325,53 @@ impl TryFrom<&Row<'_>> for Contracts {
             address: row.get(1)?,
             signers: row.get(2)?,
             transaction_id: row.get(3)?,
+            default_token_uri: row.get(4)?,
+            approval_threshold: row.get(5)?,
+            max_supply: row.get(6)?,
+            max_per_address: row.get(7)?,
+            total_minted: row.get(8)?,
+            paused: row.get(9)?,
+            owner: row.get(10)?,
+            pending_owner: row.get(11)?,
+            royalty_receiver: row.get(12)?,
+            royalty_bps: row.get(13)?,
+            name: row.get(14)?,
+            symbol: row.get(15)?,
+            description: row.get(16)?,
+            contract_uri: row.get(17)?,
+            metadata_frozen: row.get(18)?,
+            soulbound: row.get(19)?,
+            next_token_id: row.get(20)?,
+            clawback_disabled: row.get(21)?,
+            recipient_allowlist: row.get(22)?,
+            permit_name: row.get(23)?,
         })
     }
 }
 
 impl Contracts {
+    // Contracts are read through the contracts_with_signers view, which
+    // aggregates the contract_signers rows back into the signers list
+    const SELECT_BY_ID: &'static str = "SELECT * FROM contracts_with_signers WHERE id = ?";
+    const SELECT_BY_ADDRESS: &'static str = "SELECT * FROM contracts_with_signers WHERE address = ?";
+    const SELECT_BY_TRANSACTION_ID: &'static str =
+        "SELECT * FROM contracts_with_signers WHERE transaction_id = ?";
+    const SELECT_BY_SIGNER: &'static str =
+        "SELECT * FROM contracts_with_signers WHERE id IN (
+            SELECT contract_id FROM contract_signers WHERE signer = ?
+        ) ORDER BY id";
+    const SELECT_BY_BLOCKED: &'static str =
+        "SELECT * FROM contracts_with_signers WHERE id IN (
+            SELECT contract_id FROM blocked_addresses WHERE account = ?
+        ) ORDER BY id";
+    const SELECT_IS_BLOCKED: &'static str =
+        "SELECT 1 FROM blocked_addresses WHERE contract_id = ? AND account = ?";
+    const SELECT_IS_ALLOWED_RECIPIENT: &'static str =
+        "SELECT 1 FROM allowed_recipients WHERE contract_id = ? AND account = ?";
+
     fn get_by_id(conn: &Connection, id: i32) -> Result<Self, rusqlite::Error> {
         conn.query_row(
-            "SELECT * FROM contracts WHERE id = ?",
+            Self::SELECT_BY_ID,
             [id],
             |row| Self::try_from(row)
         )
@@ -153,7 +379,7 @@ impl Contracts {
 
     fn get_by_address(conn: &Connection, address: AddressSqlite) -> Result<Self, rusqlite::Error> {
         conn.query_row(
-            "SELECT * FROM contracts WHERE address = ?",
+            Self::SELECT_BY_ADDRESS,
             [address],
             |row| Self::try_from(row)
         )
@@ -161,368 +387,7963 @@ impl Contracts {
 
     fn get_by_transaction_id(conn: &Connection, tx_id: i32) -> Result<Self, rusqlite::Error> {
         conn.query_row(
-            "SELECT * FROM contracts WHERE transaction_id = ?",
+            Self::SELECT_BY_TRANSACTION_ID,
             [tx_id],
             |row| Self::try_from(row)
         )
     }
-}
 
-impl TryFrom<&Row<'_>> for Transactions {
-    type Error = rusqlite::Error;
+    // Returns every contract that the given address is a signer of
+    fn get_by_signer(conn: &Connection, signer: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_SIGNER)?;
+        let contracts_iter = stmt.query_map([signer], |row| Self::try_from(row))?;
 
-    fn try_from(row: &Row) -> Result<Self, Self::Error> {
-        Ok(Transactions {
-            id: row.get(0)?,
-            sender: row.get(1)?,
-            transaction_type: row.get(2)?,
-            data: row.get(3)?,
-            timestamp: row.get(4)?,
-        })
+        contracts_iter.collect::<Result<Vec<_>, _>>()
     }
-}
 
-impl Transactions {
-    fn get_by_id(conn: &Connection, id: i32) -> Result<Self, rusqlite::Error> {
-        conn.query_row(
-            "SELECT * FROM transactions WHERE id = ?",
-            [id],
-            |row| Self::try_from(row)
-        )
-    }
+    // Returns every contract that blocks the given address
+    fn get_by_blocked(conn: &Connection, account: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_BLOCKED)?;
+        let contracts_iter = stmt.query_map([account], |row| Self::try_from(row))?;
 
-    fn get_by_sender(conn: &Connection, sender: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
-        let mut stmt = conn.prepare("SELECT * FROM transactions WHERE sender = ?")?;
-        let transactions_iter = stmt.query_map([sender], |row| Self::try_from(row))?;
-        
-        transactions_iter.collect::<Result<Vec<_>, _>>()
+        contracts_iter.collect::<Result<Vec<_>, _>>()
     }
 
-    fn get_by_type(conn: &Connection, tx_type: TransactionType) -> Result<Vec<Self>, rusqlite::Error> {
-        let mut stmt = conn.prepare("SELECT * FROM transactions WHERE transaction_type = ?")?;
-        let transactions_iter = stmt.query_map([tx_type], |row| Self::try_from(row))?;
-        
-        transactions_iter.collect::<Result<Vec<_>, _>>()
+    fn is_blocked(&self, conn: &Connection, account: AddressSqlite) -> Result<bool, rusqlite::Error> {
+        conn.query_row(Self::SELECT_IS_BLOCKED, (self.id, account), |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|row| row.is_some())
     }
 
-    fn get_by_type_and_sender(
-        conn: &Connection, 
-        tx_type: TransactionType,
-        sender: AddressSqlite
-    ) -> Result<Vec<Self>, rusqlite::Error> {
-        let mut stmt = conn.prepare(
-            "SELECT * FROM transactions WHERE transaction_type = :type AND sender = :sender"
-        )?;
-        let transactions_iter = stmt.query_map(
-            named_params! {":type": tx_type, ":sender": sender},
-            |row| Self::try_from(row)
-        )?;
-        
-        transactions_iter.collect::<Result<Vec<_>, _>>()
+    // Every address may receive tokens unless the recipient allowlist is on
+    fn is_allowed_recipient(&self, conn: &Connection, account: AddressSqlite) -> Result<bool, rusqlite::Error> {
+        if !self.recipient_allowlist {
+            return Ok(true);
+        }
+        conn.query_row(Self::SELECT_IS_ALLOWED_RECIPIENT, (self.id, account), |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|row| row.is_some())
     }
 
-    fn get_by_type_after_timestamp(
-        conn: &Connection,
-        tx_type: TransactionType,
-        timestamp: i64
-    ) -> Result<Vec<Self>, rusqlite::Error> {
-        let mut stmt = conn.prepare(
-            "SELECT * FROM transactions WHERE transaction_type = :type AND timestamp > :ts"
-        )?;
-        let transactions_iter = stmt.query_map(
-            named_params! {":type": tx_type, ":ts": timestamp},
-            |row| Self::try_from(row)
-        )?;
-        
-        transactions_iter.collect::<Result<Vec<_>, _>>()
-    }
-}
+    // Returns the per-id URI if one is set, falling back to the default URI
+    fn token_uri(&self, conn: &Connection, id: U256) -> Result<Option<String>, rusqlite::Error> {
+        let uri: Option<String> = conn.query_row(
+            "SELECT uri FROM token_uris WHERE contract_id = ? AND token_id = ?",
+            (self.id, U256Sqlite::from(id)),
+            |row| row.get(0)
+        ).optional()?;
 
-#[derive(Debug, thiserror::Error)]
-pub enum DatabaseError {
-    #[error("Database error: {0}")]
-    SqliteError(#[from] rusqlite::Error),
-    #[error("Invalid transaction type: {0}")]
-    InvalidTransactionType(String),
-    #[error("Invalid transaction data: {0}")]
-    InvalidTransactionData(String),
-}
+        Ok(uri.or_else(|| self.default_token_uri.clone()))
+    }
 
-fn main() -> Result<(), DatabaseError> {
-    let conn = initialize_db()?;
-    Ok(())
-}
+    // A token's URI is frozen if the whole contract or the token itself is
+    fn is_token_metadata_frozen(&self, conn: &Connection, id: U256) -> Result<bool, rusqlite::Error> {
+        if self.metadata_frozen {
+            return Ok(true);
+        }
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM frozen_tokens WHERE contract_id = ? AND token_id = ?)",
+            (self.id, U256Sqlite::from(id)),
+            |row| row.get(0)
+        )
+    }
 
-fn initialize_db() -> Result<Connection, DatabaseError> {
-    let conn = Connection::open_in_memory()?;
-    
-    // Register custom functions first
-    conn.create_scalar_function(
-        "derive_contract_address",
-        1,
-        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
-        |ctx| {
-            let transaction_id: i64 = ctx.get::<i64>(0)?;
-            
-            // CREATE2 address derivation
-            // address = keccak256(0xff ++ deployerAddress ++ salt ++ keccak256(initCode))[12:]
-            
-            // Using a fixed deployer address and init code for this example
-            // In production, these should be parameters or configured constants
-            // TODO: Change to sender of bridge address
-            let deployer = AddressSqlite::from(
-                Address::from_str("0x4000000000000000000000000000000000000000").unwrap()
-            );
-            
-            // This should be your actual contract init code
-            // TODO: Change to ERC-721/20/1155 init code
-            let init_code = hex::decode("0000000000000000000000000000000000000000000000000000000000000000").unwrap();
-            
-            // Calculate keccak256(initCode)
-            let init_code_hash = keccak256(&init_code);
-            
-            // Prepare the CREATE2 input buffer
-            let mut buffer = Vec::with_capacity(85); // 1 + 20 + 32 + 32
-            buffer.push(0xff);
-            buffer.extend_from_slice(deployer.0.as_slice());
-            
-            // Use transaction_id as salt, padded to 32 bytes
-            let mut salt = [0u8; 32];
-            // We want to pad the address to the right so that transaction ID comes at the end
-            salt[24..32].copy_from_slice(&transaction_id.to_be_bytes());
-            buffer.extend_from_slice(&salt);
-            
-            buffer.extend_from_slice(init_code_hash.as_slice());
-            
-            // Calculate final hash and take last 20 bytes for the address
-            let address_bytes = &keccak256(&buffer)[12..];
-            Ok(address_bytes.to_vec())
+    // Returns the holder of an ERC-721 token, whether it was minted on its
+    // own or as part of a range
+    fn owner_of(&self, conn: &Connection, id: U256) -> Result<Option<AddressSqlite>, rusqlite::Error> {
+        let owner: Option<AddressSqlite> = conn.query_row(
+            "SELECT owner FROM balances WHERE contract_id = ? AND token_id = ? LIMIT 1",
+            (self.id, U256Sqlite::from(id)),
+            |row| row.get(0)
+        ).optional()?;
+        if owner.is_some() {
+            return Ok(owner);
         }
-    )?;
 
-    // Change ID to use the ID from the smart contract once written
-    // For now we'll auto-increment for testing purposes, but later on we'll use
-    // the ID from the smart contract
-    conn.execute(
-        "CREATE TABLE transactions(
-            id    INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender BLOB NOT NULL,
-            transaction_type TEXT NOT NULL,
-            data  BLOB,
-            timestamp INTEGER NOT NULL
-        )",
-        (), // empty list of parameters.
-    )?;
+        Ok(TokenRanges::get_covering(conn, self.id, id)?.map(|range| range.owner))
+    }
 
-    // Create a table for contract addresses
-    // Contract addresses are unique. Transactions and contracts are 1:1 and also unique
-    conn.execute(
-        "CREATE TABLE contracts(
-            id    INTEGER PRIMARY KEY AUTOINCREMENT,
-            address BLOB NOT NULL UNIQUE,
-            signers BLOB,
-            transaction_id INTEGER NOT NULL UNIQUE
-        )",
-        (),
-    )?;
+    // Like owner_of, but only for ids that behave as ERC-721 tokens: a single
+    // holder with a balance of exactly 1. Ids minted in ranges always do
+    fn nft_owner_of(&self, conn: &Connection, id: U256) -> Result<Option<AddressSqlite>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT owner, amount FROM balances WHERE contract_id = ? AND token_id = ? LIMIT 2"
+        )?;
+        let holders = stmt.query_map((self.id, U256Sqlite::from(id)), |row| {
+            Ok((row.get::<_, AddressSqlite>(0)?, row.get::<_, U256Sqlite>(1)?))
+        })?.collect::<Result<Vec<_>, _>>()?;
 
-    // Create a trigger to automatically create a new contract when a
-    // TransactionType of CreateToken is inserted. Uses a custom function to
-    // derive the contract address from the transaction ID
-    // Down the road, this can be updated with a salt so that the contract is
-    // synced with CREATE2
-    conn.execute(
-        "CREATE TRIGGER create_contract_trigger AFTER INSERT ON transactions
-        WHEN NEW.transaction_type = 'CreateToken'
-        BEGIN
-            INSERT INTO contracts (address, signers, transaction_id) 
-            VALUES (derive_contract_address(NEW.id), NEW.sender, NEW.id);
-        END",
-        (),
-    )?;
+        match holders.as_slice() {
+            [] => Ok(TokenRanges::get_covering(conn, self.id, id)?.map(|range| range.owner)),
+            [(owner, amount)] if amount.0 == U256::from(1) => Ok(Some(*owner)),
+            _ => Ok(None),
+        }
+    }
 
-    Ok(conn)
-}
+    // First id at or after next_token_id that has not been minted, either
+    // on its own or as part of a range. Burned ids are never handed out again
+    fn next_unminted_id(&self, conn: &Connection) -> Result<U256, DatabaseError> {
+        let mut id = self.next_token_id.0;
+        loop {
+            if let Some(range) = TokenRanges::get_covering(conn, self.id, id)? {
+                id = range.end_id.0;
+            } else if !conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM balances WHERE contract_id = ?1 AND token_id = ?2)
+                OR EXISTS(SELECT 1 FROM burned_tokens WHERE contract_id = ?1 AND token_id = ?2)",
+                (self.id, U256Sqlite::from(id)),
+                |row| row.get::<_, bool>(0)
+            )? {
+                return Ok(id);
+            }
+            id = id.checked_add(U256::from(1))
+                .ok_or_else(|| DatabaseError::MintLimitExceeded(format!("{} has no ids left", self.address)))?;
+        }
+    }
 
-// Connection must be mutable because commitments mutate the connection
-fn insert_transaction(conn: &mut Connection, transaction: &Transactions) -> Result<(), DatabaseError> {
-    // Start a new transaction
-    let tx = conn.transaction()?;
+    // A token is soulbound if the whole contract or the token itself is
+    fn is_soulbound(&self, conn: &Connection, id: U256) -> Result<bool, rusqlite::Error> {
+        if self.soulbound {
+            return Ok(true);
+        }
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM soulbound_tokens WHERE contract_id = ? AND token_id = ?)",
+            (self.id, U256Sqlite::from(id)),
+            |row| row.get(0)
+        )
+    }
 
-    // Rust enums are checked at compile time, so we don't need to check that
-    // the transaction type is valid
+    // Amount that can still be minted, or None if the supply is uncapped
+    fn remaining_supply(&self) -> Option<U256> {
+        self.max_supply.map(|max| max.0.saturating_sub(self.total_minted.0))
+    }
 
-    tx.execute(
-        "INSERT INTO transactions (sender, transaction_type, data, timestamp) VALUES (?1, ?2, ?3, ?4)",
-        (&transaction.sender, &transaction.transaction_type, &transaction.data, &transaction.timestamp),
-    )?;
+    // Total amount minted to a recipient, as counted against max_per_address
+    fn minted_to(&self, conn: &Connection, recipient: AddressSqlite) -> Result<U256, rusqlite::Error> {
+        let amount: Option<U256Sqlite> = conn.query_row(
+            "SELECT amount FROM mint_counts WHERE contract_id = ? AND recipient = ?",
+            (self.id, recipient),
+            |row| row.get(0)
+        ).optional()?;
 
-    // Commit the transaction
-    tx.commit()?;
+        Ok(amount.map_or(U256::ZERO, |amount| amount.0))
+    }
+}
 
-    Ok(())
+// One row per role held by a signer. An address is a signer of a contract
+// for as long as it holds at least one role on it
+#[derive(Debug)]
+struct ContractSigners {
+    id: i32,
+    contract_id: i32,
+    signer: AddressSqlite,
+    role: Role,
+    // The transaction that granted the role
+    transaction_id: i32,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl TryFrom<&Row<'_>> for ContractSigners {
+    type Error = rusqlite::Error;
 
-    #[test]
-    fn test_main() {
-        assert!(main().is_ok());
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(ContractSigners {
+            id: row.get(0)?,
+            contract_id: row.get(1)?,
+            signer: row.get(2)?,
+            role: row.get(3)?,
+            transaction_id: row.get(4)?,
+        })
     }
+}
 
-    #[test]
-    fn test_insert_transaction() -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = initialize_db()?;
-        let sender = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
-        let test_data = "0x".as_bytes().to_vec();
-        let test_timestamp = 1715136000;
+impl ContractSigners {
+    fn get_by_contract_id(conn: &Connection, contract_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM contract_signers WHERE contract_id = ? ORDER BY id"
+        )?;
+        let signers_iter = stmt.query_map([contract_id], |row| Self::try_from(row))?;
 
+        signers_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn is_signer(conn: &Connection, contract_id: i32, signer: AddressSqlite) -> Result<bool, rusqlite::Error> {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM contract_signers WHERE contract_id = ? AND signer = ?)",
+            (contract_id, signer),
+            |row| row.get(0)
+        )
+    }
+
+    fn has_role(
+        conn: &Connection,
+        contract_id: i32,
+        signer: AddressSqlite,
+        role: Role
+    ) -> Result<bool, rusqlite::Error> {
+        conn.query_row(
+            "SELECT EXISTS(
+                SELECT 1 FROM contract_signers WHERE contract_id = ? AND signer = ? AND role = ?
+            )",
+            (contract_id, signer, role),
+            |row| row.get(0)
+        )
+    }
+
+    fn count_signers(conn: &Connection, contract_id: i32) -> Result<i64, rusqlite::Error> {
+        conn.query_row(
+            "SELECT COUNT(DISTINCT signer) FROM contract_signers WHERE contract_id = ?",
+            [contract_id],
+            |row| row.get(0)
+        )
+    }
+
+    fn count_with_role(conn: &Connection, contract_id: i32, role: Role) -> Result<i64, rusqlite::Error> {
+        conn.query_row(
+            "SELECT COUNT(*) FROM contract_signers WHERE contract_id = ? AND role = ?",
+            (contract_id, role),
+            |row| row.get(0)
+        )
+    }
+}
+
+#[derive(Debug)]
+struct OwnershipHistory {
+    id: i32,
+    contract_id: i32,
+    // None for the creation of the contract
+    previous_owner: Option<AddressSqlite>,
+    new_owner: AddressSqlite,
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for OwnershipHistory {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(OwnershipHistory {
+            id: row.get(0)?,
+            contract_id: row.get(1)?,
+            previous_owner: row.get(2)?,
+            new_owner: row.get(3)?,
+            transaction_id: row.get(4)?,
+        })
+    }
+}
+
+impl OwnershipHistory {
+    fn get_by_contract_id(conn: &Connection, contract_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare("SELECT * FROM ownership_history WHERE contract_id = ? ORDER BY id")?;
+        let history_iter = stmt.query_map([contract_id], |row| Self::try_from(row))?;
+
+        history_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// Logs emitted by transactions, stored with the same topics and data an EVM
+// log would carry
+#[derive(Debug)]
+struct Events {
+    id: i32,
+    transaction_id: i32,
+    contract_id: i32,
+    // The Solidity event name, for readability in SQL
+    name: String,
+    // Concatenated 32-byte topics, starting with the event signature hash
+    topics: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl TryFrom<&Row<'_>> for Events {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Events {
+            id: row.get(0)?,
+            transaction_id: row.get(1)?,
+            contract_id: row.get(2)?,
+            name: row.get(3)?,
+            topics: row.get(4)?,
+            data: row.get(5)?,
+        })
+    }
+}
+
+impl Events {
+    fn get_by_transaction_id(conn: &Connection, transaction_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare("SELECT * FROM events WHERE transaction_id = ? ORDER BY id")?;
+        let events_iter = stmt.query_map([transaction_id], |row| Self::try_from(row))?;
+
+        events_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get_by_contract_id(conn: &Connection, contract_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare("SELECT * FROM events WHERE contract_id = ? ORDER BY id")?;
+        let events_iter = stmt.query_map([contract_id], |row| Self::try_from(row))?;
+
+        events_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn decode<E: SolEvent>(&self) -> Result<E, alloy::sol_types::Error> {
+        let topics = self.topics.chunks_exact(32).map(alloy::primitives::B256::from_slice);
+        E::decode_raw_log(topics, &self.data, true)
+    }
+}
+
+// Snapshot of a contract's metadata after each change, starting with the
+// values set at creation
+#[derive(Debug)]
+struct ContractMetadataHistory {
+    id: i32,
+    contract_id: i32,
+    name: String,
+    symbol: String,
+    description: String,
+    contract_uri: Option<String>,
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for ContractMetadataHistory {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(ContractMetadataHistory {
+            id: row.get(0)?,
+            contract_id: row.get(1)?,
+            name: row.get(2)?,
+            symbol: row.get(3)?,
+            description: row.get(4)?,
+            contract_uri: row.get(5)?,
+            transaction_id: row.get(6)?,
+        })
+    }
+}
+
+impl ContractMetadataHistory {
+    fn get_by_contract_id(conn: &Connection, contract_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM contract_metadata_history WHERE contract_id = ? ORDER BY id"
+        )?;
+        let history_iter = stmt.query_map([contract_id], |row| Self::try_from(row))?;
+
+        history_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// A sensitive operation awaiting approval from the contract's signers
+#[derive(Debug)]
+struct Proposals {
+    id: i32,
+    contract_id: i32,
+    proposer: AddressSqlite,
+    transaction_type: TransactionType,
+    data: Vec<u8>,
+    expires_at: i64,
+    // The transaction that created the proposal
+    transaction_id: i32,
+    executed_transaction_id: Option<i32>,
+}
+
+impl TryFrom<&Row<'_>> for Proposals {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Proposals {
+            id: row.get(0)?,
+            contract_id: row.get(1)?,
+            proposer: row.get(2)?,
+            transaction_type: row.get(3)?,
+            data: row.get(4)?,
+            expires_at: row.get(5)?,
+            transaction_id: row.get(6)?,
+            executed_transaction_id: row.get(7)?,
+        })
+    }
+}
+
+impl Proposals {
+    fn get_by_id(conn: &Connection, id: i32) -> Result<Self, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM proposals WHERE id = ?",
+            [id],
+            |row| Self::try_from(row)
+        )
+    }
+
+    fn get_by_contract_id(conn: &Connection, contract_id: i32) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare("SELECT * FROM proposals WHERE contract_id = ? ORDER BY id")?;
+        let proposals_iter = stmt.query_map([contract_id], |row| Self::try_from(row))?;
+
+        proposals_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    // Proposals expire once block time reaches `expires_at`
+    fn is_expired(&self, ctx: &Context) -> bool {
+        ctx.has_reached(self.expires_at)
+    }
+
+    // Only approvals from addresses that are still signers count towards the
+    // threshold
+    fn count_approvals(&self, conn: &Connection) -> Result<i64, rusqlite::Error> {
+        conn.query_row(
+            "SELECT COUNT(*) FROM proposal_approvals
+            WHERE proposal_id = ?1 AND signer IN (
+                SELECT signer FROM contract_signers WHERE contract_id = ?2
+            )",
+            (self.id, self.contract_id),
+            |row| row.get(0)
+        )
+    }
+}
+
+// A session key acts on behalf of the signer that delegated it, limited to
+// the listed contracts and transaction types, up to max_mints minted tokens
+// and until expires_at
+#[derive(Debug)]
+struct SessionKeys {
+    id: i32,
+    session_key: AddressSqlite,
+    delegator: AddressSqlite,
+    max_mints: U256Sqlite,
+    mints: U256Sqlite,
+    expires_at: i64,
+    // The transaction that delegated the key
+    transaction_id: i32,
+    revoked_transaction_id: Option<i32>,
+    // Set once the key itself accepts the delegation with AcceptSessionKey.
+    // Until then its transactions are its own
+    accepted_transaction_id: Option<i32>,
+}
+
+impl TryFrom<&Row<'_>> for SessionKeys {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(SessionKeys {
+            id: row.get(0)?,
+            session_key: row.get(1)?,
+            delegator: row.get(2)?,
+            max_mints: row.get(3)?,
+            mints: row.get(4)?,
+            expires_at: row.get(5)?,
+            transaction_id: row.get(6)?,
+            revoked_transaction_id: row.get(7)?,
+            accepted_transaction_id: row.get(8)?,
+        })
+    }
+}
+
+impl SessionKeys {
+    // Returns the most recent delegation of the given key, if it was ever
+    // delegated
+    fn get_latest_by_session_key(
+        conn: &Connection,
+        session_key: AddressSqlite
+    ) -> Result<Option<Self>, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM session_keys WHERE session_key = ? ORDER BY id DESC LIMIT 1",
+            [session_key],
+            |row| Self::try_from(row)
+        ).optional()
+    }
+
+    fn is_expired(&self, ctx: &Context) -> bool {
+        ctx.has_reached(self.expires_at)
+    }
+
+    // Whether transactions from the key are currently applied as the delegator
+    fn is_active(&self, ctx: &Context) -> bool {
+        self.accepted_transaction_id.is_some() && self.revoked_transaction_id.is_none() && !self.is_expired(ctx)
+    }
+
+    fn allows(
+        &self,
+        conn: &Connection,
+        contract_id: i32,
+        transaction_type: &TransactionType
+    ) -> Result<bool, rusqlite::Error> {
+        conn.query_row(
+            "SELECT
+                EXISTS(SELECT 1 FROM session_key_contracts WHERE session_key_id = ?1 AND contract_id = ?2)
+                AND EXISTS(SELECT 1 FROM session_key_types WHERE session_key_id = ?1 AND transaction_type = ?3)",
+            (self.id, contract_id, transaction_type),
+            |row| row.get(0)
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Balances {
+    contract_id: i32,
+    token_id: U256Sqlite,
+    owner: AddressSqlite,
+    amount: U256Sqlite,
+}
+
+impl TryFrom<&Row<'_>> for Balances {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Balances {
+            contract_id: row.get(0)?,
+            token_id: row.get(1)?,
+            owner: row.get(2)?,
+            amount: row.get(3)?,
+        })
+    }
+}
+
+impl Balances {
+    fn balance_of(
+        conn: &Connection,
+        contract_id: i32,
+        owner: AddressSqlite,
+        token_id: U256
+    ) -> Result<U256, rusqlite::Error> {
+        let amount: Option<U256Sqlite> = conn.query_row(
+            "SELECT amount FROM balances WHERE contract_id = ? AND token_id = ? AND owner = ?",
+            (contract_id, U256Sqlite::from(token_id), owner),
+            |row| row.get(0)
+        ).optional()?;
+        let amount = amount.map_or(U256::ZERO, |amount| amount.0);
+
+        // Tokens still held in a minted range count as a balance of one
+        match TokenRanges::get_covering(conn, contract_id, token_id)? {
+            Some(range) if range.owner == owner => Ok(amount.saturating_add(U256::from(1))),
+            _ => Ok(amount),
+        }
+    }
+
+    fn get_by_owner(conn: &Connection, owner: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM balances WHERE owner = ? ORDER BY contract_id, token_id"
+        )?;
+        let balances_iter = stmt.query_map([owner], |row| Self::try_from(row))?;
+
+        balances_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// The window of a mint phase on a contract. Phases without a row are closed.
+// Windows are checked against block timestamps
+#[derive(Debug)]
+struct MintPhases {
+    contract_id: i32,
+    phase: MintPhase,
+    // Root of the allowlist Merkle tree. Only used by the Allowlist phase
+    merkle_root: Option<Vec<u8>>,
+    starts_at: i64,
+    // Zero when the phase never ends
+    ends_at: i64,
+    transaction_id: i32,
+    // Limits on the amount minted during this phase, in total and per
+    // recipient. None when the phase is only limited by the contract's caps
+    max_supply: Option<U256Sqlite>,
+    max_per_address: Option<U256Sqlite>,
+    minted: U256Sqlite,
+    // Whether the phase only mints ids nobody holds yet
+    unique_ids: bool,
+}
+
+impl TryFrom<&Row<'_>> for MintPhases {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(MintPhases {
+            contract_id: row.get(0)?,
+            phase: row.get(1)?,
+            merkle_root: row.get(2)?,
+            starts_at: row.get(3)?,
+            ends_at: row.get(4)?,
+            transaction_id: row.get(5)?,
+            max_supply: row.get(6)?,
+            max_per_address: row.get(7)?,
+            minted: row.get(8)?,
+            unique_ids: row.get(9)?,
+        })
+    }
+}
+
+impl MintPhases {
+    fn get(conn: &Connection, contract_id: i32, phase: MintPhase) -> Result<Option<Self>, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM mint_phases WHERE contract_id = ? AND phase = ?",
+            (contract_id, phase),
+            |row| Self::try_from(row)
+        ).optional()
+    }
+
+    fn is_open(&self, block_timestamp: i64) -> bool {
+        block_timestamp >= self.starts_at && (self.ends_at == 0 || block_timestamp < self.ends_at)
+    }
+
+    // Amount minted to a recipient during this phase
+    fn minted_to(&self, conn: &Connection, recipient: AddressSqlite) -> Result<U256, rusqlite::Error> {
+        let amount: Option<U256Sqlite> = conn.query_row(
+            "SELECT amount FROM phase_mint_counts WHERE contract_id = ? AND phase = ? AND recipient = ?",
+            (self.contract_id, self.phase, recipient),
+            |row| row.get(0)
+        ).optional()?;
+
+        Ok(amount.map_or(U256::ZERO, |amount| amount.0))
+    }
+}
+
+// The outcome of an applied transaction, returned by insert_transaction and
+// kept for later lookups. token_id is the id allocated by MintNext, and
+// clawback is set when the transaction moved tokens with ForceTransfer
+#[derive(Debug, PartialEq)]
+struct Receipts {
+    transaction_id: i32,
+    token_id: Option<U256Sqlite>,
+    clawback: bool,
+}
+
+impl TryFrom<&Row<'_>> for Receipts {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Receipts {
+            transaction_id: row.get(0)?,
+            token_id: row.get(1)?,
+            clawback: row.get(2)?,
+        })
+    }
+}
+
+impl Receipts {
+    fn get_by_transaction_id(conn: &Connection, transaction_id: i32) -> Result<Self, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM receipts WHERE transaction_id = ?",
+            [transaction_id],
+            |row| Self::try_from(row)
+        )
+    }
+}
+
+// ERC-4907 users. A user can use a token, e.g. a rented game item, without
+// owning it until `expires`. The row is deleted when the token is transferred
+#[derive(Debug)]
+struct TokenUsers {
+    contract_id: i32,
+    token_id: U256Sqlite,
+    user: AddressSqlite,
+    expires: i64,
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for TokenUsers {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TokenUsers {
+            contract_id: row.get(0)?,
+            token_id: row.get(1)?,
+            user: row.get(2)?,
+            expires: row.get(3)?,
+            transaction_id: row.get(4)?,
+        })
+    }
+}
+
+impl TokenUsers {
+    const SELECT_BY_USER: &'static str = "SELECT * FROM token_users WHERE user = ? ORDER BY contract_id, token_id";
+
+    fn get(conn: &Connection, contract_id: i32, token_id: U256) -> Result<Option<Self>, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM token_users WHERE contract_id = ? AND token_id = ?",
+            (contract_id, U256Sqlite::from(token_id)),
+            |row| Self::try_from(row)
+        ).optional()
+    }
+
+    // Every token the address has been made the user of, including expired
+    // ones that have not been transferred since
+    fn get_by_user(conn: &Connection, user: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_USER)?;
+        let users_iter = stmt.query_map([user], |row| Self::try_from(row))?;
+
+        users_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    // As in ERC-4907, the user keeps the role up to and including `expires`
+    fn is_active(&self, timestamp: i64) -> bool {
+        self.expires >= timestamp
+    }
+}
+
+// Game state kept per token, such as an item's level or durability. Values
+// are kept as the exact text that was set, so "007" stays "007"
+#[derive(Debug, PartialEq)]
+struct TokenAttributes {
+    contract_id: i32,
+    token_id: U256Sqlite,
+    key: String,
+    value: String,
+    // The value as a number for range filters, None unless the value parses
+    // as a finite number
+    number: Option<f64>,
+    // The transaction that last set the value
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for TokenAttributes {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TokenAttributes {
+            contract_id: row.get(0)?,
+            token_id: row.get(1)?,
+            key: row.get(2)?,
+            value: row.get(3)?,
+            number: row.get(4)?,
+            transaction_id: row.get(5)?,
+        })
+    }
+}
+
+impl TokenAttributes {
+    const SELECT_BY_KEY_AT_LEAST: &'static str =
+        "SELECT * FROM token_attributes WHERE contract_id = ? AND key = ? AND number >= ? ORDER BY number";
+
+    fn get_by_token(conn: &Connection, contract_id: i32, token_id: U256) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare("SELECT * FROM token_attributes WHERE contract_id = ? AND token_id = ? ORDER BY key")?;
+        let attributes_iter = stmt.query_map((contract_id, U256Sqlite::from(token_id)), |row| Self::try_from(row))?;
+
+        attributes_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get(conn: &Connection, contract_id: i32, token_id: U256, key: &str) -> Result<Option<Self>, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM token_attributes WHERE contract_id = ? AND token_id = ? AND key = ?",
+            (contract_id, U256Sqlite::from(token_id), key),
+            |row| Self::try_from(row)
+        ).optional()
+    }
+
+    // Tokens whose numeric `key` attribute is at least `min`, lowest first
+    fn get_by_key_at_least(
+        conn: &Connection,
+        contract_id: i32,
+        key: &str,
+        min: f64
+    ) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_KEY_AT_LEAST)?;
+        let attributes_iter = stmt.query_map((contract_id, key, min), |row| Self::try_from(row))?;
+
+        attributes_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// Every value an attribute has had, oldest first. A NULL value records the
+// removal of the attribute
+#[derive(Debug)]
+struct TokenAttributeHistory {
+    id: i32,
+    contract_id: i32,
+    token_id: U256Sqlite,
+    key: String,
+    value: Option<String>,
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for TokenAttributeHistory {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TokenAttributeHistory {
+            id: row.get(0)?,
+            contract_id: row.get(1)?,
+            token_id: row.get(2)?,
+            key: row.get(3)?,
+            value: row.get(4)?,
+            transaction_id: row.get(5)?,
+        })
+    }
+}
+
+impl TokenAttributeHistory {
+    const SELECT_BY_TOKEN: &'static str =
+        "SELECT * FROM token_attribute_history WHERE contract_id = ? AND token_id = ? ORDER BY id";
+
+    fn get_by_token(conn: &Connection, contract_id: i32, token_id: U256) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_TOKEN)?;
+        let history_iter = stmt.query_map((contract_id, U256Sqlite::from(token_id)), |row| Self::try_from(row))?;
+
+        history_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// ERC-20 allowances, separate from the per-token and operator approvals used
+// for NFTs. Rows with a zero amount are deleted rather than kept
+#[derive(Debug)]
+struct Allowances {
+    contract_id: i32,
+    owner: AddressSqlite,
+    spender: AddressSqlite,
+    amount: U256Sqlite,
+}
+
+impl TryFrom<&Row<'_>> for Allowances {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Allowances {
+            contract_id: row.get(0)?,
+            owner: row.get(1)?,
+            spender: row.get(2)?,
+            amount: row.get(3)?,
+        })
+    }
+}
+
+impl Allowances {
+    fn allowance(
+        conn: &Connection,
+        contract_id: i32,
+        owner: AddressSqlite,
+        spender: AddressSqlite
+    ) -> Result<U256, rusqlite::Error> {
+        let amount: Option<U256Sqlite> = conn.query_row(
+            "SELECT amount FROM allowances WHERE contract_id = ? AND owner = ? AND spender = ?",
+            (contract_id, owner, spender),
+            |row| row.get(0)
+        ).optional()?;
+
+        Ok(amount.map_or(U256::ZERO, |amount| amount.0))
+    }
+
+    fn get_by_owner(conn: &Connection, contract_id: i32, owner: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM allowances WHERE contract_id = ? AND owner = ? ORDER BY spender"
+        )?;
+        let allowances_iter = stmt.query_map((contract_id, owner), |row| Self::try_from(row))?;
+
+        allowances_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// Contiguous ERC-721 ids minted together by MintRange, from start_id to
+// end_id inclusive. A range stands for one token per id held by its owner
+// until an id is transferred or burned, at which point that id is split out
+// into the balances table
+#[derive(Debug)]
+struct TokenRanges {
+    contract_id: i32,
+    start_id: U256Sqlite,
+    end_id: U256Sqlite,
+    owner: AddressSqlite,
+    transaction_id: i32,
+}
+
+impl TryFrom<&Row<'_>> for TokenRanges {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(TokenRanges {
+            contract_id: row.get(0)?,
+            start_id: row.get(1)?,
+            end_id: row.get(2)?,
+            owner: row.get(3)?,
+            transaction_id: row.get(4)?,
+        })
+    }
+}
+
+impl TokenRanges {
+    // Ranges never overlap, so the only range that can cover an id is the
+    // last one starting at or before it
+    fn get_covering(conn: &Connection, contract_id: i32, id: U256) -> Result<Option<Self>, rusqlite::Error> {
+        let range = conn.query_row(
+            "SELECT * FROM token_ranges WHERE contract_id = ? AND start_id <= ?
+            ORDER BY start_id DESC LIMIT 1",
+            (contract_id, U256Sqlite::from(id)),
+            |row| Self::try_from(row)
+        ).optional()?;
+
+        Ok(range.filter(|range| range.end_id.0 >= id))
+    }
+
+    fn get_by_owner(conn: &Connection, owner: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(
+            "SELECT * FROM token_ranges WHERE owner = ? ORDER BY contract_id, start_id"
+        )?;
+        let ranges_iter = stmt.query_map([owner], |row| Self::try_from(row))?;
+
+        ranges_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn len(&self) -> U256 {
+        self.end_id.0 - self.start_id.0 + U256::from(1)
+    }
+}
+
+// Blocks are produced by the node, which stamps them with the time it
+// includes transactions at. Unlike Transactions.timestamp, which is supplied
+// by the client, block timestamps can be trusted for scheduling
+#[derive(Debug, PartialEq)]
+struct Blocks {
+    number: i64,
+    timestamp: i64,
+}
+
+impl TryFrom<&Row<'_>> for Blocks {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Blocks {
+            number: row.get(0)?,
+            timestamp: row.get(1)?,
+        })
+    }
+}
+
+// Block time before the node has produced its first block. Client
+// timestamps are never used in their place, so until then time stands at
+// the genesis: nothing has expired yet, and only mint phases starting at 0
+// are open
+const GENESIS_TIMESTAMP: i64 = 0;
+
+impl Blocks {
+    // The block that transactions are currently being included in
+    fn get_latest(conn: &Connection) -> Result<Option<Self>, rusqlite::Error> {
+        conn.query_row(
+            "SELECT * FROM blocks ORDER BY number DESC LIMIT 1",
+            [],
+            |row| Self::try_from(row)
+        ).optional()
+    }
+}
+
+impl TryFrom<&Row<'_>> for Transactions {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &Row) -> Result<Self, Self::Error> {
+        Ok(Transactions {
+            id: row.get(0)?,
+            sender: row.get(1)?,
+            transaction_type: row.get(2)?,
+            data: row.get(3)?,
+            timestamp: row.get(4)?,
+            signature: row.get(5)?,
+            relayer: row.get(6)?,
+            relayer_signature: row.get(7)?,
+            block_number: row.get(8)?,
+        })
+    }
+}
+
+impl Transactions {
+    // Queries are kept as constants so that the query plan tests exercise the
+    // exact SQL the getters run
+    const SELECT_BY_ID: &'static str = "SELECT * FROM transactions WHERE id = ?";
+    const SELECT_BY_SENDER: &'static str = "SELECT * FROM transactions WHERE sender = ?";
+    const SELECT_BY_TYPE: &'static str = "SELECT * FROM transactions WHERE transaction_type = ?";
+    const SELECT_BY_TYPE_AND_SENDER: &'static str =
+        "SELECT * FROM transactions WHERE transaction_type = :type AND sender = :sender";
+    const SELECT_BY_TYPE_AFTER_TIMESTAMP: &'static str =
+        "SELECT * FROM transactions WHERE transaction_type = :type AND timestamp > :ts";
+    const SELECT_BY_RELAYER: &'static str = "SELECT * FROM transactions WHERE relayer = ?";
+
+    fn get_by_id(conn: &Connection, id: i32) -> Result<Self, rusqlite::Error> {
+        conn.query_row(
+            Self::SELECT_BY_ID,
+            [id],
+            |row| Self::try_from(row)
+        )
+    }
+
+    fn get_by_sender(conn: &Connection, sender: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_SENDER)?;
+        let transactions_iter = stmt.query_map([sender], |row| Self::try_from(row))?;
+        
+        transactions_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    // Returns the transactions a relayer submitted on behalf of their senders
+    fn get_by_relayer(conn: &Connection, relayer: AddressSqlite) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_RELAYER)?;
+        let transactions_iter = stmt.query_map([relayer], |row| Self::try_from(row))?;
+
+        transactions_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get_by_type(conn: &Connection, tx_type: TransactionType) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_TYPE)?;
+        let transactions_iter = stmt.query_map([tx_type], |row| Self::try_from(row))?;
+        
+        transactions_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get_by_type_and_sender(
+        conn: &Connection, 
+        tx_type: TransactionType,
+        sender: AddressSqlite
+    ) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_TYPE_AND_SENDER)?;
+        let transactions_iter = stmt.query_map(
+            named_params! {":type": tx_type, ":sender": sender},
+            |row| Self::try_from(row)
+        )?;
+        
+        transactions_iter.collect::<Result<Vec<_>, _>>()
+    }
+
+    fn get_by_type_after_timestamp(
+        conn: &Connection,
+        tx_type: TransactionType,
+        timestamp: i64
+    ) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.prepare(Self::SELECT_BY_TYPE_AFTER_TIMESTAMP)?;
+        let transactions_iter = stmt.query_map(
+            named_params! {":type": tx_type, ":ts": timestamp},
+            |row| Self::try_from(row)
+        )?;
+        
+        transactions_iter.collect::<Result<Vec<_>, _>>()
+    }
+}
+
+// A transaction signed by its sender and submitted by a relayer. The sender
+// is recovered from the signature, so the relayer can neither act as anyone
+// else nor alter the operation. `nonce` is the sender's nonce the signature
+// was made over. The relayer signs the same hash, so an address is only
+// recorded as the relayer of transactions it actually submitted
+#[derive(Debug)]
+struct MetaTransaction {
+    relayer: AddressSqlite,
+    transaction_type: TransactionType,
+    data: Vec<u8>,
+    nonce: u64,
+    signature: Vec<u8>,
+    relayer_signature: Vec<u8>,
+    timestamp: i64,
+}
+
+// ABI-encoded payloads carried in `Transactions.data`. Each transaction type
+// that the processor acts on has a matching struct here
+mod payload {
+    alloy::sol! {
+        // A cap of zero means uncapped. An empty CreateToken payload creates
+        // an uncapped, transferable contract
+        #[derive(Debug, PartialEq, Default)]
+        struct CreateToken {
+            uint256 maxSupply;
+            uint256 maxPerAddress;
+            string name;
+            string symbol;
+            bool soulbound;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct AddTokenSigner {
+            address token;
+            address signer;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct RemoveTokenSigner {
+            address token;
+            address signer;
+        }
+
+        // `role` is the name of a Role variant, e.g. "Minter"
+        #[derive(Debug, PartialEq)]
+        struct GrantRole {
+            address token;
+            address account;
+            string role;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct RevokeRole {
+            address token;
+            address account;
+            string role;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetDefaultTokenURI {
+            address token;
+            string uri;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetTokenURIPerId {
+            address token;
+            uint256 id;
+            string uri;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Mint {
+            address token;
+            address to;
+            uint256 id;
+            uint256 amount;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Transfer {
+            address token;
+            address from;
+            address to;
+            uint256 id;
+            uint256 amount;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Burn {
+            address token;
+            address from;
+            uint256 id;
+            uint256 amount;
+        }
+
+        // Approves `spender` to move `owner`'s balance of `id`
+        #[derive(Debug, PartialEq)]
+        struct Approve {
+            address token;
+            address owner;
+            address spender;
+            uint256 id;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetApprovalForAll {
+            address token;
+            address operator;
+            bool approved;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetApprovalThreshold {
+            address token;
+            uint64 threshold;
+        }
+
+        // Proposes a sensitive operation on `token`. `transactionType` names
+        // the TransactionType and `data` carries its payload
+        #[derive(Debug, PartialEq)]
+        struct ProposeOperation {
+            address token;
+            string transactionType;
+            bytes data;
+            uint64 expiresAt;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct ApproveProposal {
+            address token;
+            uint64 proposalId;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct ExecuteProposal {
+            address token;
+            uint64 proposalId;
+        }
+
+        // Lets `sessionKey` act as the sender on `tokens`, for the named
+        // transaction types only
+        #[derive(Debug, PartialEq)]
+        struct DelegateSessionKey {
+            address sessionKey;
+            address[] tokens;
+            string[] transactionTypes;
+            uint256 maxMints;
+            uint64 expiresAt;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct RevokeSessionKey {
+            address sessionKey;
+        }
+
+        // Sent by the session key itself to consent to the latest delegation
+        // from `delegator`
+        #[derive(Debug, PartialEq)]
+        struct AcceptSessionKey {
+            address delegator;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Pause {
+            address token;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct Unpause {
+            address token;
+        }
+
+        // Nominates `newOwner`, who must accept before ownership changes.
+        // Nominating the zero address cancels a pending transfer
+        #[derive(Debug, PartialEq)]
+        struct TransferOwnership {
+            address token;
+            address newOwner;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct AcceptOwnership {
+            address token;
+        }
+
+        // Royalties are in basis points of the sale price. A zero receiver
+        // removes the royalty
+        #[derive(Debug, PartialEq)]
+        struct SetDefaultRoyalty {
+            address token;
+            address receiver;
+            uint16 basisPoints;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetTokenRoyalty {
+            address token;
+            uint256 id;
+            address receiver;
+            uint16 basisPoints;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetContractURI {
+            address token;
+            string uri;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct SetContractMetadata {
+            address token;
+            string name;
+            string symbol;
+            string description;
+        }
+
+        // Freezes every token URI on the contract when `allTokens` is set,
+        // otherwise only the URI of `id`
+        #[derive(Debug, PartialEq)]
+        struct FreezeMetadata {
+            address token;
+            bool allTokens;
+            uint256 id;
+        }
+
+        // Mints `quantity` ERC-721 ids starting at `startId` to `to` as a
+        // single range
+        #[derive(Debug, PartialEq)]
+        struct MintRange {
+            address token;
+            address to;
+            uint256 startId;
+            uint256 quantity;
+        }
+
+        // Mints to the next unminted id of the contract, which is returned in
+        // the receipt
+        #[derive(Debug, PartialEq)]
+        struct MintNext {
+            address token;
+            address to;
+            uint256 amount;
+        }
+
+        // Off-chain authorization from a minter to mint `amount` of `id`,
+        // signed as EIP-712 typed data. A zero `to` leaves the recipient open,
+        // in which case whoever redeems the voucher receives the tokens
+        #[derive(Debug, PartialEq)]
+        struct MintVoucher {
+            address token;
+            address to;
+            uint256 id;
+            uint256 amount;
+            uint64 expiresAt;
+            uint256 nonce;
+        }
+
+        // `signature` is the 65-byte signature over the voucher's EIP-712
+        // signing hash. Anyone may submit it
+        #[derive(Debug, PartialEq)]
+        struct RedeemVoucher {
+            MintVoucher voucher;
+            bytes signature;
+        }
+
+        // Allows or disallows `relayer` to submit meta-transactions that
+        // target the contract
+        #[derive(Debug, PartialEq)]
+        struct SetRelayer {
+            address token;
+            address relayer;
+            bool allowed;
+        }
+
+        // Opens `phase` ("Allowlist" or "Public") between the block
+        // timestamps `startsAt` and `endsAt`. An `endsAt` of zero leaves the
+        // phase open indefinitely. Caps of zero leave the phase uncapped.
+        // With `uniqueIds` set, as for ERC-721 drops, the phase only mints
+        // ids nobody holds yet. Leave it unset for editions and ERC-20 mints
+        #[derive(Debug, PartialEq)]
+        struct SetMintPhase {
+            address token;
+            string phase;
+            bytes32 merkleRoot;
+            uint64 startsAt;
+            uint64 endsAt;
+            uint256 maxSupply;
+            uint256 maxPerAddress;
+            bool uniqueIds;
+        }
+
+        // Mints `amount` of `id` to the sender, who must be on the allowlist
+        // for up to `quantity` of it. Leaves are
+        // keccak256(keccak256(abi.encode(account, id, quantity))), as built by
+        // OpenZeppelin's StandardMerkleTree
+        #[derive(Debug, PartialEq)]
+        struct ClaimAllowlist {
+            address token;
+            uint256 id;
+            uint256 amount;
+            uint256 quantity;
+            bytes32[] proof;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct PublicMint {
+            address token;
+            uint256 id;
+            uint256 amount;
+        }
+
+        // ERC-20 approve. Lets `spender` move up to `amount` of the sender's
+        // fungible balance. An amount of type(uint256).max never runs out
+        #[derive(Debug, PartialEq)]
+        struct SetAllowance {
+            address token;
+            address spender;
+            uint256 amount;
+        }
+
+        // EIP-2612 permit. Sets `owner`'s allowance for `spender` to `value`
+        // with the owner's signature over a typed_data::Permit. Anyone may
+        // submit it
+        #[derive(Debug, PartialEq)]
+        struct Permit {
+            address token;
+            address owner;
+            address spender;
+            uint256 value;
+            uint256 deadline;
+            uint8 v;
+            bytes32 r;
+            bytes32 s;
+        }
+
+        // ERC-20 transferFrom, spending the sender's allowance from `from`
+        #[derive(Debug, PartialEq)]
+        struct TransferFrom {
+            address token;
+            address from;
+            address to;
+            uint256 amount;
+        }
+
+        // Permanently makes `id` non-transferable
+        #[derive(Debug, PartialEq)]
+        struct MarkSoulbound {
+            address token;
+            uint256 id;
+        }
+
+        // Moves `amount` of `id` from `from` to `to` without the holder's
+        // approval, e.g. to recover items from a compromised account
+        #[derive(Debug, PartialEq)]
+        struct ForceTransfer {
+            address token;
+            address from;
+            address to;
+            uint256 id;
+            uint256 amount;
+        }
+
+        // Permanently turns off ForceTransfer on the contract
+        #[derive(Debug, PartialEq)]
+        struct DisableClawback {
+            address token;
+        }
+
+        // Adds `account` to or removes it from the contract's blocklist.
+        // Blocked addresses can neither send nor receive tokens
+        #[derive(Debug, PartialEq)]
+        struct SetBlocked {
+            address token;
+            address account;
+            bool blocked;
+        }
+
+        // Adds `account` to or removes it from the recipient allowlist
+        #[derive(Debug, PartialEq)]
+        struct SetAllowedRecipient {
+            address token;
+            address account;
+            bool allowed;
+        }
+
+        // Turns enforcement of the recipient allowlist on or off
+        #[derive(Debug, PartialEq)]
+        struct SetRecipientAllowlist {
+            address token;
+            bool enabled;
+        }
+
+        #[derive(Debug, PartialEq)]
+        struct TokenAttribute {
+            string key;
+            string value;
+        }
+
+        // Sets attributes of `id`, leaving its other attributes as they are.
+        // An empty value removes the attribute
+        #[derive(Debug, PartialEq)]
+        struct SetTokenAttributes {
+            address token;
+            uint256 id;
+            TokenAttribute[] attributes;
+        }
+
+        // ERC-4907 setUser. Makes `user` the user of `id` until the block
+        // timestamp passes `expires`. The zero address clears the user
+        #[derive(Debug, PartialEq)]
+        struct SetUser {
+            address token;
+            uint256 id;
+            address user;
+            uint64 expires;
+        }
+    }
+
+    // Earlier versions of payloads, from before fields were added to them.
+    // They keep their names so that signatures over them stay valid, see
+    // decode_create_token and decode_set_mint_phase
+    pub mod v1 {
+        alloy::sol! {
+            #[derive(Debug, PartialEq)]
+            struct CreateToken {
+                uint256 maxSupply;
+                uint256 maxPerAddress;
+            }
+
+            #[derive(Debug, PartialEq)]
+            struct SetMintPhase {
+                address token;
+                string phase;
+                bytes32 merkleRoot;
+                uint64 startsAt;
+                uint64 endsAt;
+                uint256 maxSupply;
+                uint256 maxPerAddress;
+            }
+        }
+    }
+
+    pub mod v2 {
+        alloy::sol! {
+            #[derive(Debug, PartialEq)]
+            struct CreateToken {
+                uint256 maxSupply;
+                uint256 maxPerAddress;
+                string name;
+                string symbol;
+            }
+        }
+    }
+
+    impl From<v1::CreateToken> for CreateToken {
+        fn from(payload: v1::CreateToken) -> Self {
+            CreateToken { maxSupply: payload.maxSupply, maxPerAddress: payload.maxPerAddress, ..Default::default() }
+        }
+    }
+
+    impl From<v1::SetMintPhase> for SetMintPhase {
+        fn from(payload: v1::SetMintPhase) -> Self {
+            SetMintPhase {
+                token: payload.token,
+                phase: payload.phase,
+                merkleRoot: payload.merkleRoot,
+                startsAt: payload.startsAt,
+                endsAt: payload.endsAt,
+                maxSupply: payload.maxSupply,
+                maxPerAddress: payload.maxPerAddress,
+                uniqueIds: false,
+            }
+        }
+    }
+
+    impl From<v2::CreateToken> for CreateToken {
+        fn from(payload: v2::CreateToken) -> Self {
+            CreateToken {
+                maxSupply: payload.maxSupply,
+                maxPerAddress: payload.maxPerAddress,
+                name: payload.name,
+                symbol: payload.symbol,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+// EIP-712 structs signed off-chain that are defined by other standards and
+// so have to keep their names and fields exactly
+mod typed_data {
+    alloy::sol! {
+        // EIP-2612
+        #[derive(Debug, PartialEq)]
+        struct Permit {
+            address owner;
+            address spender;
+            uint256 value;
+            uint256 nonce;
+            uint256 deadline;
+        }
+    }
+}
+
+// Read-only functions served through eth_call against a contract address
+mod calls {
+    alloy::sol! {
+        interface IMintVM {
+            function supportsInterface(bytes4 interfaceId) external view returns (bool);
+            function royaltyInfo(uint256 tokenId, uint256 salePrice)
+                external view returns (address receiver, uint256 royaltyAmount);
+            function name() external view returns (string);
+            function symbol() external view returns (string);
+            function contractURI() external view returns (string);
+            function locked(uint256 tokenId) external view returns (bool);
+            function ownerOf(uint256 tokenId) external view returns (address);
+            function balanceOf(address account) external view returns (uint256);
+            function allowance(address owner, address spender) external view returns (uint256);
+            function nonces(address owner) external view returns (uint256);
+            function DOMAIN_SEPARATOR() external view returns (bytes32);
+            function userOf(uint256 tokenId) external view returns (address);
+            function userExpires(uint256 tokenId) external view returns (uint256);
+        }
+    }
+}
+
+// Events emitted by transactions, see the events table
+mod events {
+    alloy::sol! {
+        // Freezing counterparts of the EIP-4906 MetadataUpdate and
+        // BatchMetadataUpdate events. Once emitted, the URIs of the given
+        // tokens never change again
+        #[derive(Debug, PartialEq)]
+        event MetadataFrozen(uint256 _tokenId);
+
+        #[derive(Debug, PartialEq)]
+        event BatchMetadataFrozen(uint256 _fromTokenId, uint256 _toTokenId);
+
+        // ERC-5192 minimal soulbound event, emitted when an id is marked
+        #[derive(Debug, PartialEq)]
+        event Locked(uint256 tokenId);
+
+        // Emitted for every ForceTransfer, next to what a regular transfer
+        // would record, so holders can audit each use of the Clawback role
+        #[derive(Debug, PartialEq)]
+        event Clawback(
+            address indexed operator,
+            address indexed from,
+            address indexed to,
+            uint256 id,
+            uint256 amount
+        );
+
+        #[derive(Debug, PartialEq)]
+        event ClawbackDisabled();
+
+        // Emitted for every change to the transfer blocklist and recipient
+        // allowlist, so holders can see who was restricted and when
+        #[derive(Debug, PartialEq)]
+        event BlockedSet(address indexed account, bool blocked);
+
+        #[derive(Debug, PartialEq)]
+        event AllowedRecipientSet(address indexed account, bool allowed);
+
+        #[derive(Debug, PartialEq)]
+        event RecipientAllowlistSet(bool enabled);
+
+        // ERC-4907, emitted when a user is set and when a transfer clears it
+        #[derive(Debug, PartialEq)]
+        event UpdateUser(uint256 indexed tokenId, address indexed user, uint64 expires);
+    }
+}
+
+// ERC-165 interface ids reported by supportsInterface
+const INTERFACE_ID_ERC165: [u8; 4] = [0x01, 0xff, 0xc9, 0xa7];
+const INTERFACE_ID_ERC2981: [u8; 4] = [0x2a, 0x55, 0x20, 0x5a];
+const INTERFACE_ID_ERC5192: [u8; 4] = [0xb4, 0x5a, 0x3c, 0x0e];
+const INTERFACE_ID_ERC4907: [u8; 4] = [0xad, 0x09, 0x2b, 0x5c];
+const SUPPORTED_INTERFACES: &[[u8; 4]] = &[
+    INTERFACE_ID_ERC165,
+    INTERFACE_ID_ERC2981,
+    INTERFACE_ID_ERC5192,
+    INTERFACE_ID_ERC4907,
+];
+
+// EIP-712 domain name and version of MintVM contracts
+const EIP712_NAME: &str = "MintVM";
+const EIP712_VERSION: &str = "1";
+
+// Chain id used by initialize_db. Other instances, such as testnet and
+// mainnet, pass their own to initialize_db_with_chain_id so that signatures
+// made for one are never valid on another
+const DEFAULT_CHAIN_ID: u64 = 1337;
+
+// Fungible balances are held under this token id, so that ERC-20 allowances,
+// TransferFrom and balanceOf(address) all act on the same balance
+const ERC20_TOKEN_ID: U256 = U256::ZERO;
+
+// Royalty basis points are out of 10000, as in ERC-2981 implementations
+const ROYALTY_DENOMINATOR: u64 = 10_000;
+
+fn decode_payload<T: SolValue + From<<T::SolType as alloy::sol_types::SolType>::RustType>>(
+    data: &[u8]
+) -> Result<T, DatabaseError> {
+    T::abi_decode(data, true)
+        .map_err(|e| DatabaseError::InvalidTransactionData(e.to_string()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatabaseError {
+    #[error("Database error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error("Invalid transaction type: {0}")]
+    InvalidTransactionType(String),
+    #[error("Invalid transaction data: {0}")]
+    InvalidTransactionData(String),
+    #[error("Unknown contract: {0}")]
+    UnknownContract(Address),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Insufficient balance: {0}")]
+    InsufficientBalance(String),
+    #[error("Operation on {0} requires an approved multisig proposal")]
+    MultisigRequired(Address),
+    #[error("Proposal {0} has expired")]
+    ProposalExpired(i32),
+    #[error("Session key {0} has expired")]
+    SessionKeyExpired(Address),
+    #[error("Session key {0} has been revoked")]
+    SessionKeyRevoked(Address),
+    #[error("Mint limit exceeded: {0}")]
+    MintLimitExceeded(String),
+    #[error("Contract {0} is paused")]
+    ContractPaused(Address),
+    #[error("Invalid call: {0}")]
+    InvalidCall(String),
+    #[error("Metadata is frozen: {0}")]
+    MetadataFrozen(String),
+    #[error("Token is soulbound: {0}")]
+    Soulbound(String),
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("Voucher {0} has expired")]
+    VoucherExpired(U256),
+    #[error("Voucher {0} has already been redeemed")]
+    VoucherRedeemed(U256),
+    #[error("Mint phase is not open: {0}")]
+    MintPhaseClosed(String),
+    #[error("Invalid Merkle proof: {0}")]
+    InvalidMerkleProof(String),
+    #[error("Invalid block: {0}")]
+    InvalidBlock(String),
+    #[error("Insufficient allowance: {0}")]
+    InsufficientAllowance(String),
+    #[error("Permit expired at {0}")]
+    PermitExpired(U256),
+    #[error("Clawback is disabled on {0}")]
+    ClawbackDisabled(Address),
+    #[error("Address is blocked: {0}")]
+    AddressBlocked(String),
+    #[error("Recipient is not allowlisted: {0}")]
+    RecipientNotAllowed(String),
+}
+
+fn main() -> Result<(), DatabaseError> {
+    let conn = initialize_db()?;
+    Ok(())
+}
+
+fn initialize_db() -> Result<Connection, DatabaseError> {
+    initialize_db_with_chain_id(DEFAULT_CHAIN_ID)
+}
+
+fn initialize_db_with_chain_id(chain_id: u64) -> Result<Connection, DatabaseError> {
+    let conn = Connection::open_in_memory()?;
+    
+    // Register custom functions first
+    conn.create_scalar_function(
+        "derive_contract_address",
+        1,
+        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let transaction_id: i64 = ctx.get::<i64>(0)?;
+            
+            // CREATE2 address derivation
+            // address = keccak256(0xff ++ deployerAddress ++ salt ++ keccak256(initCode))[12:]
+            
+            // Using a fixed deployer address and init code for this example
+            // In production, these should be parameters or configured constants
+            // TODO: Change to sender of bridge address
+            let deployer = AddressSqlite::from(
+                Address::from_str("0x4000000000000000000000000000000000000000").unwrap()
+            );
+            
+            // This should be your actual contract init code
+            // TODO: Change to ERC-721/20/1155 init code
+            let init_code = hex::decode("0000000000000000000000000000000000000000000000000000000000000000").unwrap();
+            
+            // Calculate keccak256(initCode)
+            let init_code_hash = keccak256(&init_code);
+            
+            // Prepare the CREATE2 input buffer
+            let mut buffer = Vec::with_capacity(85); // 1 + 20 + 32 + 32
+            buffer.push(0xff);
+            buffer.extend_from_slice(deployer.0.as_slice());
+            
+            // Use transaction_id as salt, padded to 32 bytes
+            let mut salt = [0u8; 32];
+            // We want to pad the address to the right so that transaction ID comes at the end
+            salt[24..32].copy_from_slice(&transaction_id.to_be_bytes());
+            buffer.extend_from_slice(&salt);
+            
+            buffer.extend_from_slice(init_code_hash.as_slice());
+            
+            // Calculate final hash and take last 20 bytes for the address
+            let address_bytes = &keccak256(&buffer)[12..];
+            Ok(address_bytes.to_vec())
+        }
+    )?;
+
+    conn.create_aggregate_function(
+        "address_list",
+        1,
+        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
+        AddressListAggregate,
+    )?;
+
+    // Change ID to use the ID from the smart contract once written
+    // For now we'll auto-increment for testing purposes, but later on we'll use
+    // the ID from the smart contract
+    conn.execute(
+        "CREATE TABLE transactions(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender BLOB NOT NULL,
+            transaction_type TEXT NOT NULL,
+            data  BLOB,
+            timestamp INTEGER NOT NULL,
+            signature BLOB,
+            relayer BLOB,
+            relayer_signature BLOB,
+            block_number INTEGER REFERENCES blocks(number)
+        )",
+        (), // empty list of parameters.
+    )?;
+
+    conn.execute(
+        "CREATE TABLE blocks(
+            number INTEGER PRIMARY KEY,
+            timestamp INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    // The chain id signatures are bound to, as a single row
+    conn.execute(
+        "CREATE TABLE chain_config(
+            chain_id INTEGER NOT NULL
+        )",
+        (),
+    )?;
+    conn.execute(
+        "INSERT INTO chain_config (chain_id) VALUES (?1)",
+        [chain_id as i64],
+    )?;
+
+    // Number of signed transactions applied per sender. The next signed
+    // transaction from a sender must be signed over this nonce
+    conn.execute(
+        "CREATE TABLE sender_nonces(
+            sender BLOB PRIMARY KEY,
+            nonce INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    // Secondary indexes for the transaction getters. Each getter filters on
+    // transaction_type and/or sender, so the composite indexes lead with the
+    // equality column and end with the column used for the range or second
+    // equality match. get_by_type is served by the prefix of either
+    // composite index
+    conn.execute(
+        "CREATE INDEX idx_transactions_sender ON transactions(sender)",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_transactions_type_sender ON transactions(transaction_type, sender)",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_transactions_type_timestamp ON transactions(transaction_type, timestamp)",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_transactions_relayer ON transactions(relayer)",
+        (),
+    )?;
+
+    // Create a table for contract addresses
+    // Contract addresses are unique. Transactions and contracts are 1:1 and also unique
+    conn.execute(
+        "CREATE TABLE contracts(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            address BLOB NOT NULL UNIQUE,
+            transaction_id INTEGER NOT NULL UNIQUE,
+            default_token_uri TEXT,
+            approval_threshold INTEGER NOT NULL DEFAULT 1,
+            max_supply BLOB,
+            max_per_address BLOB,
+            total_minted BLOB NOT NULL DEFAULT (zeroblob(32)),
+            paused INTEGER NOT NULL DEFAULT 0,
+            owner BLOB NOT NULL,
+            pending_owner BLOB,
+            royalty_receiver BLOB,
+            royalty_bps INTEGER,
+            name TEXT NOT NULL DEFAULT '',
+            symbol TEXT NOT NULL DEFAULT '',
+            description TEXT NOT NULL DEFAULT '',
+            contract_uri TEXT,
+            metadata_frozen INTEGER NOT NULL DEFAULT 0,
+            soulbound INTEGER NOT NULL DEFAULT 0,
+            next_token_id BLOB NOT NULL
+                DEFAULT (x'0000000000000000000000000000000000000000000000000000000000000001'),
+            clawback_disabled INTEGER NOT NULL DEFAULT 0,
+            recipient_allowlist INTEGER NOT NULL DEFAULT 0,
+            permit_name TEXT NOT NULL DEFAULT ''
+        )",
+        (),
+    )?;
+
+    // One receipt per committed transaction
+    conn.execute(
+        "CREATE TABLE receipts(
+            transaction_id INTEGER PRIMARY KEY REFERENCES transactions(id),
+            token_id BLOB,
+            clawback INTEGER NOT NULL DEFAULT 0
+        )",
+        (),
+    )?;
+
+    // Every change of owner, starting with the creation of the contract
+    conn.execute(
+        "CREATE TABLE ownership_history(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            previous_owner BLOB,
+            new_owner BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_ownership_history_contract ON ownership_history(contract_id)",
+        (),
+    )?;
+
+    // Signers are stored one row per (contract, signer, role) so that SQL can
+    // answer which contracts an address can sign for, and so that granting or
+    // revoking a role only touches that row
+    conn.execute(
+        "CREATE TABLE contract_signers(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            signer BLOB NOT NULL,
+            role TEXT NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            UNIQUE(contract_id, signer, role)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_contract_signers_signer ON contract_signers(signer)",
+        (),
+    )?;
+
+    // Compatibility view that exposes the signers of each contract as the
+    // concatenated address list previously stored on contracts, in the order
+    // the signers were added
+    conn.execute(
+        "CREATE VIEW contracts_with_signers AS
+        SELECT
+            contracts.id,
+            contracts.address,
+            (
+                SELECT address_list(signer) FROM (
+                    SELECT signer FROM contract_signers
+                    WHERE contract_signers.contract_id = contracts.id
+                    GROUP BY signer
+                    ORDER BY MIN(contract_signers.id)
+                )
+            ) AS signers,
+            contracts.transaction_id,
+            contracts.default_token_uri,
+            contracts.approval_threshold,
+            contracts.max_supply,
+            contracts.max_per_address,
+            contracts.total_minted,
+            contracts.paused,
+            contracts.owner,
+            contracts.pending_owner,
+            contracts.royalty_receiver,
+            contracts.royalty_bps,
+            contracts.name,
+            contracts.symbol,
+            contracts.description,
+            contracts.contract_uri,
+            contracts.metadata_frozen,
+            contracts.soulbound,
+            contracts.next_token_id,
+            contracts.clawback_disabled,
+            contracts.recipient_allowlist,
+            contracts.permit_name
+        FROM contracts",
+        (),
+    )?;
+
+    // Create a trigger to automatically create a new contract when a
+    // TransactionType of CreateToken is inserted. Uses a custom function to
+    // derive the contract address from the transaction ID
+    // Down the road, this can be updated with a salt so that the contract is
+    // synced with CREATE2
+    // The sender of the CreateToken transaction owns the contract, becomes
+    // the first signer and holds every role
+    conn.execute(
+        "CREATE TRIGGER create_contract_trigger AFTER INSERT ON transactions
+        WHEN NEW.transaction_type = 'CreateToken'
+        BEGIN
+            INSERT INTO contracts (address, transaction_id, owner) 
+            VALUES (derive_contract_address(NEW.id), NEW.id, NEW.sender);
+            INSERT INTO ownership_history (contract_id, previous_owner, new_owner, transaction_id)
+            SELECT id, NULL, NEW.sender, NEW.id FROM contracts WHERE transaction_id = NEW.id;
+            INSERT INTO contract_signers (contract_id, signer, role, transaction_id)
+            SELECT contracts.id, NEW.sender, roles.role, NEW.id
+            FROM contracts, (
+                SELECT 'Admin' AS role UNION ALL SELECT 'Minter' UNION ALL SELECT 'UriManager'
+            ) AS roles
+            WHERE contracts.transaction_id = NEW.id;
+        END",
+        (),
+    )?;
+
+    // Tokens whose URI was frozen individually. Contract-wide freezes are
+    // recorded on contracts.metadata_frozen instead
+    conn.execute(
+        "CREATE TABLE frozen_tokens(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    // Ids marked soulbound individually. Soulbound contracts are recorded on
+    // contracts.soulbound instead
+    conn.execute(
+        "CREATE TABLE soulbound_tokens(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    // Ids whose last holder burned them, so MintNext never hands them out
+    // again. `transaction_id` is the burn that removed the last of the id
+    conn.execute(
+        "CREATE TABLE burned_tokens(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE events(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            transaction_id INTEGER NOT NULL,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            name TEXT NOT NULL,
+            topics BLOB NOT NULL,
+            data BLOB NOT NULL
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_events_transaction ON events(transaction_id)",
+        (),
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_events_contract ON events(contract_id)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE contract_metadata_history(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            name TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            description TEXT NOT NULL,
+            contract_uri TEXT,
+            transaction_id INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_contract_metadata_history_contract ON contract_metadata_history(contract_id)",
+        (),
+    )?;
+
+    // Per-token royalties that override the contract default
+    conn.execute(
+        "CREATE TABLE token_royalties(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            receiver BLOB NOT NULL,
+            bps INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    // Amount minted to each recipient, for contracts with a per-address limit
+    conn.execute(
+        "CREATE TABLE mint_counts(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            recipient BLOB NOT NULL,
+            amount BLOB NOT NULL,
+            PRIMARY KEY(contract_id, recipient)
+        )",
+        (),
+    )?;
+
+    // Token state. Balances cover ERC-20 (a single id), ERC-721 (amounts of
+    // one) and ERC-1155. Rows with a zero amount are deleted rather than kept
+    conn.execute(
+        "CREATE TABLE balances(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            owner BLOB NOT NULL,
+            amount BLOB NOT NULL,
+            PRIMARY KEY(contract_id, token_id, owner)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_balances_owner ON balances(owner)",
+        (),
+    )?;
+
+    // Ranges minted by MintRange that have not been split into balances yet.
+    // Rows are keyed by their first id so that the range covering an id is
+    // found with a single index seek
+    conn.execute(
+        "CREATE TABLE token_ranges(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            start_id BLOB NOT NULL,
+            end_id BLOB NOT NULL,
+            owner BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, start_id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_token_ranges_owner ON token_ranges(owner)",
+        (),
+    )?;
+
+    // Vouchers are single use. Nonces are scoped to the contract, so each
+    // contract's minters share one nonce space
+    conn.execute(
+        "CREATE TABLE redeemed_vouchers(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            nonce BLOB NOT NULL,
+            signer BLOB NOT NULL,
+            recipient BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, nonce)
+        )",
+        (),
+    )?;
+
+    // Relayers allowed to submit meta-transactions for each contract. A row
+    // exists for as long as the relayer is allowed
+    conn.execute(
+        "CREATE TABLE contract_relayers(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            relayer BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, relayer)
+        )",
+        (),
+    )?;
+
+    // Compliance lists. A row exists for as long as the address is blocked
+    // or allowlisted. The account indexes answer which contracts list an
+    // address
+    conn.execute(
+        "CREATE TABLE blocked_addresses(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            account BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, account)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_blocked_addresses_account ON blocked_addresses(account)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE allowed_recipients(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            account BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, account)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_allowed_recipients_account ON allowed_recipients(account)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE mint_phases(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            phase TEXT NOT NULL,
+            merkle_root BLOB,
+            starts_at INTEGER NOT NULL,
+            ends_at INTEGER NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            max_supply BLOB,
+            max_per_address BLOB,
+            minted BLOB NOT NULL DEFAULT (zeroblob(32)),
+            unique_ids INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY(contract_id, phase)
+        )",
+        (),
+    )?;
+
+    // Amount minted to each recipient during a phase, for phases with a
+    // per-address limit
+    conn.execute(
+        "CREATE TABLE phase_mint_counts(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            phase TEXT NOT NULL,
+            recipient BLOB NOT NULL,
+            amount BLOB NOT NULL,
+            PRIMARY KEY(contract_id, phase, recipient)
+        )",
+        (),
+    )?;
+
+    // Amount claimed per allowlist leaf. Leaves are kept across root changes,
+    // so re-publishing a leaf does not let it be claimed twice
+    conn.execute(
+        "CREATE TABLE allowlist_claims(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            leaf BLOB NOT NULL,
+            claimed BLOB NOT NULL,
+            PRIMARY KEY(contract_id, leaf)
+        )",
+        (),
+    )?;
+
+    // Per-token approvals. Cleared whenever the owner transfers the token
+    conn.execute(
+        "CREATE TABLE token_approvals(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            owner BLOB NOT NULL,
+            spender BLOB NOT NULL,
+            PRIMARY KEY(contract_id, token_id, owner)
+        )",
+        (),
+    )?;
+
+    // Current attributes of each token. The (contract_id, key, number) index
+    // serves filters such as every token with a level of at least 10
+    conn.execute(
+        "CREATE TABLE token_attributes(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            number REAL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id, key)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_token_attributes_key_number ON token_attributes(contract_id, key, number)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE token_attribute_history(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT,
+            transaction_id INTEGER NOT NULL
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_token_attribute_history_token ON token_attribute_history(contract_id, token_id)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE token_users(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            user BLOB NOT NULL,
+            expires INTEGER NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_token_users_user ON token_users(user)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE allowances(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            owner BLOB NOT NULL,
+            spender BLOB NOT NULL,
+            amount BLOB NOT NULL,
+            PRIMARY KEY(contract_id, owner, spender)
+        )",
+        (),
+    )?;
+
+    // Number of permits used per owner, as returned by nonces(owner)
+    conn.execute(
+        "CREATE TABLE permit_nonces(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            owner BLOB NOT NULL,
+            nonce INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, owner)
+        )",
+        (),
+    )?;
+
+    // A row exists for as long as the operator is approved
+    conn.execute(
+        "CREATE TABLE operator_approvals(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            owner BLOB NOT NULL,
+            operator BLOB NOT NULL,
+            PRIMARY KEY(contract_id, owner, operator)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE token_uris(
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            token_id BLOB NOT NULL,
+            uri TEXT NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(contract_id, token_id)
+        )",
+        (),
+    )?;
+
+    // Multisig proposals for sensitive operations. A proposal is pending until
+    // executed_transaction_id is set, and expires once block time reaches
+    // expires_at
+    conn.execute(
+        "CREATE TABLE proposals(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            proposer BLOB NOT NULL,
+            transaction_type TEXT NOT NULL,
+            data BLOB NOT NULL,
+            expires_at INTEGER NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            executed_transaction_id INTEGER
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_proposals_contract ON proposals(contract_id)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE proposal_approvals(
+            proposal_id INTEGER NOT NULL REFERENCES proposals(id),
+            signer BLOB NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            PRIMARY KEY(proposal_id, signer)
+        )",
+        (),
+    )?;
+
+    // Session keys delegated by signers. The scope of each delegation lives in
+    // session_key_contracts and session_key_types
+    conn.execute(
+        "CREATE TABLE session_keys(
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            session_key BLOB NOT NULL,
+            delegator BLOB NOT NULL,
+            max_mints BLOB NOT NULL,
+            mints BLOB NOT NULL,
+            expires_at INTEGER NOT NULL,
+            transaction_id INTEGER NOT NULL,
+            revoked_transaction_id INTEGER,
+            accepted_transaction_id INTEGER
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_session_keys_session_key ON session_keys(session_key)",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE session_key_contracts(
+            session_key_id INTEGER NOT NULL REFERENCES session_keys(id),
+            contract_id INTEGER NOT NULL REFERENCES contracts(id),
+            PRIMARY KEY(session_key_id, contract_id)
+        )",
+        (),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE session_key_types(
+            session_key_id INTEGER NOT NULL REFERENCES session_keys(id),
+            transaction_type TEXT NOT NULL,
+            PRIMARY KEY(session_key_id, transaction_type)
+        )",
+        (),
+    )?;
+
+    Ok(conn)
+}
+
+// Connection must be mutable because commitments mutate the connection
+fn insert_transaction(conn: &mut Connection, transaction: &Transactions) -> Result<Receipts, DatabaseError> {
+    // Start a new transaction
+    let tx = conn.transaction()?;
+
+    // Rust enums are checked at compile time, so we don't need to check that
+    // the transaction type is valid
+
+    // Signed transactions must be signed by their sender. Unsigned ones are
+    // trusted to come from the sender as before, unless they were relayed
+    match (&transaction.signature, transaction.relayer) {
+        (Some(signature), _) => verify_transaction_signature(&tx, transaction, signature)?,
+        (None, Some(relayer)) => {
+            return Err(DatabaseError::InvalidSignature(
+                format!("transaction relayed by {} is not signed by {}", relayer, transaction.sender)
+            ));
+        }
+        (None, None) => {}
+    }
+    if let Some(relayer) = transaction.relayer {
+        require_allowed_relayer(&tx, relayer, &transaction.transaction_type, &transaction.data)?;
+    }
+
+    let block = Blocks::get_latest(&tx)?;
+    tx.execute(
+        "INSERT INTO transactions
+            (sender, transaction_type, data, timestamp, signature, relayer, relayer_signature, block_number)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            &transaction.sender,
+            &transaction.transaction_type,
+            &transaction.data,
+            &transaction.timestamp,
+            &transaction.signature,
+            &transaction.relayer,
+            &transaction.relayer_signature,
+            block.as_ref().map(|block| block.number),
+        ),
+    )?;
+    let ctx = Context {
+        transaction_id: tx.last_insert_rowid() as i32,
+        sender: transaction.sender,
+        block_timestamp: block.map(|block| block.timestamp),
+        proposal_id: None,
+    };
+    // Transactions sent by a session key act as the signer that delegated it
+    let ctx = resolve_session_key(&tx, ctx, &transaction.transaction_type, &transaction.data)?;
+    tx.execute("INSERT INTO receipts (transaction_id) VALUES (?1)", [ctx.transaction_id])?;
+
+    // Apply the transaction to the token state. Any error drops `tx` without
+    // committing, so a rejected transaction leaves no trace in the database
+    process_transaction(&tx, &ctx, &transaction.transaction_type, &transaction.data)?;
+    let receipt = Receipts::get_by_transaction_id(&tx, ctx.transaction_id)?;
+
+    // Commit the transaction
+    tx.commit()?;
+
+    Ok(receipt)
+}
+
+// Starts a new block. Transactions inserted from now on are included in it
+// and see its timestamp. Block numbers must increase and timestamps must
+// not go backwards
+fn insert_block(conn: &Connection, number: i64, timestamp: i64) -> Result<(), DatabaseError> {
+    if let Some(latest) = Blocks::get_latest(conn)? {
+        if number <= latest.number || timestamp < latest.timestamp {
+            return Err(DatabaseError::InvalidBlock(format!(
+                "block {} at {} cannot follow block {} at {}", number, timestamp, latest.number, latest.timestamp
+            )));
+        }
+    }
+
+    conn.execute(
+        "INSERT INTO blocks (number, timestamp) VALUES (?1, ?2)",
+        (number, timestamp),
+    )?;
+    Ok(())
+}
+
+// Applies a relayed transaction as a transaction from the user who signed it
+fn insert_meta_transaction(conn: &mut Connection, meta: &MetaTransaction) -> Result<Receipts, DatabaseError> {
+    let token = payload_token(&meta.transaction_type, &meta.data)?;
+    let domain = eip712_domain(chain_id(conn)?, token);
+    let hash = transaction_signing_hash(&domain, meta.nonce, &meta.transaction_type, &meta.data)?;
+    let sender = AddressSqlite::from(recover_hash_signer(&hash, &meta.signature)?);
+
+    let nonce = sender_nonce(conn, sender)?;
+    if meta.nonce != nonce {
+        return Err(DatabaseError::InvalidSignature(
+            format!("{} signed over nonce {}, expected {}", sender, meta.nonce, nonce)
+        ));
+    }
+
+    insert_transaction(conn, &Transactions {
+        id: 0,
+        sender,
+        transaction_type: meta.transaction_type.clone(),
+        data: meta.data.clone(),
+        timestamp: meta.timestamp,
+        signature: Some(meta.signature.clone()),
+        relayer: Some(meta.relayer),
+        relayer_signature: Some(meta.relayer_signature.clone()),
+        block_number: None,
+    })
+}
+
+// Relayers may only submit transactions that target a contract which allows
+// them
+fn require_allowed_relayer(
+    conn: &Connection,
+    relayer: AddressSqlite,
+    transaction_type: &TransactionType,
+    data: &[u8]
+) -> Result<(), DatabaseError> {
+    let Some(token) = payload_token(transaction_type, data)? else {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} transactions cannot be relayed", transaction_type)
+        ));
+    };
+    let contract = contract_for(conn, token)?;
+    let allowed: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM contract_relayers WHERE contract_id = ? AND relayer = ?)",
+        (contract.id, relayer),
+        |row| row.get(0)
+    )?;
+    if !allowed {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} is not an allowed relayer of {}", relayer, contract.address)
+        ));
+    }
+    Ok(())
+}
+
+fn chain_id(conn: &Connection) -> Result<u64, rusqlite::Error> {
+    conn.query_row("SELECT chain_id FROM chain_config", [], |row| row.get::<_, i64>(0))
+        .map(|chain_id| chain_id as u64)
+}
+
+fn sender_nonce(conn: &Connection, sender: AddressSqlite) -> Result<u64, rusqlite::Error> {
+    let nonce: Option<i64> = conn.query_row(
+        "SELECT nonce FROM sender_nonces WHERE sender = ?",
+        [sender],
+        |row| row.get(0)
+    ).optional()?;
+
+    Ok(nonce.map_or(0, |nonce| nonce as u64))
+}
+
+// Checks that the sender signed the transaction over their current nonce,
+// then uses the nonce up so that the signature cannot be replayed
+fn verify_transaction_signature(
+    conn: &Connection,
+    transaction: &Transactions,
+    signature: &[u8]
+) -> Result<(), DatabaseError> {
+    let token = payload_token(&transaction.transaction_type, &transaction.data)?;
+    let domain = eip712_domain(chain_id(conn)?, token);
+    let nonce = sender_nonce(conn, transaction.sender)?;
+    let hash = transaction_signing_hash(&domain, nonce, &transaction.transaction_type, &transaction.data)?;
+
+    let signer = recover_hash_signer(&hash, signature)?;
+    if signer != transaction.sender.0 {
+        return Err(DatabaseError::InvalidSignature(
+            format!("transaction from {} was signed by {}", transaction.sender, signer)
+        ));
+    }
+    if let Some(relayer) = transaction.relayer {
+        let relayer_signature = transaction.relayer_signature.as_deref().ok_or_else(|| {
+            DatabaseError::InvalidSignature(format!("transaction relayed by {} is not signed by it", relayer))
+        })?;
+        let signer = recover_hash_signer(&hash, relayer_signature)?;
+        if signer != relayer.0 {
+            return Err(DatabaseError::InvalidSignature(
+                format!("transaction relayed by {} was signed by {}", relayer, signer)
+            ));
+        }
+    }
+
+    conn.execute(
+        "INSERT INTO sender_nonces (sender, nonce) VALUES (?1, 1)
+        ON CONFLICT(sender) DO UPDATE SET nonce = nonce + 1",
+        [transaction.sender],
+    )?;
+    Ok(())
+}
+
+// EIP-712 signing hash of a transaction. The typed data wraps the payload
+// struct of the transaction type together with the sender's nonce, e.g.
+// MintVMTransaction(uint256 nonce,Mint operation)Mint(address token,...),
+// so that wallets can show the operation field by field
+fn transaction_signing_hash(
+    domain: &Eip712Domain,
+    nonce: u64,
+    transaction_type: &TransactionType,
+    data: &[u8]
+) -> Result<B256, DatabaseError> {
+    fn signing_hash<T>(domain: &Eip712Domain, nonce: u64, operation: &T) -> B256
+    where
+        T: SolStruct,
+    {
+        let hash_struct = keccak256(
+            [
+                keccak256(transaction_encode_type::<T>()).as_slice(),
+                U256::from(nonce).to_be_bytes::<32>().as_slice(),
+                operation.eip712_hash_struct().as_slice(),
+            ].concat()
+        );
+        keccak256([[0x19, 0x01].as_slice(), domain.hash_struct().as_slice(), hash_struct.as_slice()].concat())
+    }
+    fn hash<T>(domain: &Eip712Domain, nonce: u64, data: &[u8]) -> Result<B256, DatabaseError>
+    where
+        T: SolStruct + SolValue + From<<T::SolType as alloy::sol_types::SolType>::RustType>,
+    {
+        Ok(signing_hash(domain, nonce, &decode_payload::<T>(data)?))
+    }
+
+    match transaction_type {
+        TransactionType::CreateToken if data.is_empty() => {
+            Ok(signing_hash(domain, nonce, &payload::CreateToken::default()))
+        }
+        TransactionType::CreateToken => hash::<payload::CreateToken>(domain, nonce, data)
+            .or_else(|e| hash::<payload::v2::CreateToken>(domain, nonce, data).map_err(|_| e))
+            .or_else(|e| hash::<payload::v1::CreateToken>(domain, nonce, data).map_err(|_| e)),
+        TransactionType::AddTokenSigner => hash::<payload::AddTokenSigner>(domain, nonce, data),
+        TransactionType::RemoveTokenSigner => hash::<payload::RemoveTokenSigner>(domain, nonce, data),
+        TransactionType::GrantRole => hash::<payload::GrantRole>(domain, nonce, data),
+        TransactionType::RevokeRole => hash::<payload::RevokeRole>(domain, nonce, data),
+        TransactionType::SetDefaultTokenURI => hash::<payload::SetDefaultTokenURI>(domain, nonce, data),
+        TransactionType::SetTokenURIPerId => hash::<payload::SetTokenURIPerId>(domain, nonce, data),
+        TransactionType::Mint => hash::<payload::Mint>(domain, nonce, data),
+        TransactionType::Transfer => hash::<payload::Transfer>(domain, nonce, data),
+        TransactionType::Burn => hash::<payload::Burn>(domain, nonce, data),
+        TransactionType::Approve => hash::<payload::Approve>(domain, nonce, data),
+        TransactionType::SetApprovalForAll => hash::<payload::SetApprovalForAll>(domain, nonce, data),
+        TransactionType::SetApprovalThreshold => hash::<payload::SetApprovalThreshold>(domain, nonce, data),
+        TransactionType::ProposeOperation => hash::<payload::ProposeOperation>(domain, nonce, data),
+        TransactionType::ApproveProposal => hash::<payload::ApproveProposal>(domain, nonce, data),
+        TransactionType::ExecuteProposal => hash::<payload::ExecuteProposal>(domain, nonce, data),
+        TransactionType::DelegateSessionKey => hash::<payload::DelegateSessionKey>(domain, nonce, data),
+        TransactionType::RevokeSessionKey => hash::<payload::RevokeSessionKey>(domain, nonce, data),
+        TransactionType::AcceptSessionKey => hash::<payload::AcceptSessionKey>(domain, nonce, data),
+        TransactionType::Pause => hash::<payload::Pause>(domain, nonce, data),
+        TransactionType::Unpause => hash::<payload::Unpause>(domain, nonce, data),
+        TransactionType::TransferOwnership => hash::<payload::TransferOwnership>(domain, nonce, data),
+        TransactionType::AcceptOwnership => hash::<payload::AcceptOwnership>(domain, nonce, data),
+        TransactionType::SetDefaultRoyalty => hash::<payload::SetDefaultRoyalty>(domain, nonce, data),
+        TransactionType::SetTokenRoyalty => hash::<payload::SetTokenRoyalty>(domain, nonce, data),
+        TransactionType::SetContractURI => hash::<payload::SetContractURI>(domain, nonce, data),
+        TransactionType::SetContractMetadata => hash::<payload::SetContractMetadata>(domain, nonce, data),
+        TransactionType::FreezeMetadata => hash::<payload::FreezeMetadata>(domain, nonce, data),
+        TransactionType::MarkSoulbound => hash::<payload::MarkSoulbound>(domain, nonce, data),
+        TransactionType::MintRange => hash::<payload::MintRange>(domain, nonce, data),
+        TransactionType::MintNext => hash::<payload::MintNext>(domain, nonce, data),
+        TransactionType::RedeemVoucher => hash::<payload::RedeemVoucher>(domain, nonce, data),
+        TransactionType::SetRelayer => hash::<payload::SetRelayer>(domain, nonce, data),
+        TransactionType::SetMintPhase => hash::<payload::SetMintPhase>(domain, nonce, data)
+            .or_else(|e| hash::<payload::v1::SetMintPhase>(domain, nonce, data).map_err(|_| e)),
+        TransactionType::ClaimAllowlist => hash::<payload::ClaimAllowlist>(domain, nonce, data),
+        TransactionType::PublicMint => hash::<payload::PublicMint>(domain, nonce, data),
+        TransactionType::SetAllowance => hash::<payload::SetAllowance>(domain, nonce, data),
+        TransactionType::TransferFrom => hash::<payload::TransferFrom>(domain, nonce, data),
+        TransactionType::Permit => hash::<payload::Permit>(domain, nonce, data),
+        TransactionType::ForceTransfer => hash::<payload::ForceTransfer>(domain, nonce, data),
+        TransactionType::DisableClawback => hash::<payload::DisableClawback>(domain, nonce, data),
+        TransactionType::SetBlocked => hash::<payload::SetBlocked>(domain, nonce, data),
+        TransactionType::SetAllowedRecipient => hash::<payload::SetAllowedRecipient>(domain, nonce, data),
+        TransactionType::SetRecipientAllowlist => hash::<payload::SetRecipientAllowlist>(domain, nonce, data),
+        TransactionType::SetUser => hash::<payload::SetUser>(domain, nonce, data),
+        TransactionType::SetTokenAttributes => hash::<payload::SetTokenAttributes>(domain, nonce, data),
+    }
+}
+
+// EIP-712 encodeType of the MintVMTransaction wrapping a payload struct.
+// Referenced struct types follow the primary type in alphabetical order
+fn transaction_encode_type<T: SolStruct>() -> String {
+    let mut components = T::eip712_components();
+    components.push(T::eip712_root_type());
+    components.sort_unstable();
+    components.dedup();
+
+    format!("MintVMTransaction(uint256 nonce,{} operation){}", T::NAME, components.concat())
+}
+
+// The transaction being applied, as seen by the transaction handlers
+#[derive(Debug, Clone, Copy)]
+struct Context {
+    transaction_id: i32,
+    sender: AddressSqlite,
+    // Timestamp of the block the transaction is included in. None until the
+    // node has produced a block
+    block_timestamp: Option<i64>,
+    // Set while executing an approved multisig proposal
+    proposal_id: Option<i32>,
+}
+
+impl Context {
+    // Block time, which every deadline, expiry and mint window is checked
+    // against. See GENESIS_TIMESTAMP for the time before the first block
+    fn block_time(&self) -> i64 {
+        self.block_timestamp.unwrap_or(GENESIS_TIMESTAMP)
+    }
+
+    // Whether block time has reached `deadline`
+    fn has_reached(&self, deadline: i64) -> bool {
+        self.block_time() >= deadline
+    }
+}
+
+// Converts a time from a payload to the i64 block time is kept as. Times past
+// i64::MAX, such as type(uint64).max for something that never expires, are
+// stored as i64::MAX and so are never reached
+fn saturating_time(time: impl TryInto<i64>) -> i64 {
+    time.try_into().unwrap_or(i64::MAX)
+}
+
+fn process_transaction(
+    conn: &Connection,
+    ctx: &Context,
+    transaction_type: &TransactionType,
+    data: &[u8]
+) -> Result<(), DatabaseError> {
+    // Once a contract has a multisig threshold, sensitive operations can only
+    // run through an executed proposal
+    if is_sensitive(transaction_type) && ctx.proposal_id.is_none() {
+        if let Some(token) = payload_token(transaction_type, data)? {
+            let contract = contract_for(conn, token)?;
+            if contract.approval_threshold > 1 {
+                return Err(DatabaseError::MultisigRequired(token));
+            }
+        }
+    }
+
+    match transaction_type {
+        TransactionType::CreateToken => create_token(conn, ctx, data),
+        TransactionType::AddTokenSigner => add_token_signer(conn, ctx, decode_payload(data)?),
+        TransactionType::RemoveTokenSigner => remove_token_signer(conn, ctx, decode_payload(data)?),
+        TransactionType::GrantRole => grant_role(conn, ctx, decode_payload(data)?),
+        TransactionType::RevokeRole => revoke_role(conn, ctx, decode_payload(data)?),
+        TransactionType::SetDefaultTokenURI => set_default_token_uri(conn, ctx, decode_payload(data)?),
+        TransactionType::SetTokenURIPerId => set_token_uri_per_id(conn, ctx, decode_payload(data)?),
+        TransactionType::Mint => mint(conn, ctx, decode_payload(data)?),
+        TransactionType::Transfer => transfer(conn, ctx, decode_payload(data)?),
+        TransactionType::Burn => burn(conn, ctx, decode_payload(data)?),
+        TransactionType::Approve => approve(conn, ctx, decode_payload(data)?),
+        TransactionType::SetApprovalForAll => set_approval_for_all(conn, ctx, decode_payload(data)?),
+        TransactionType::SetApprovalThreshold => set_approval_threshold(conn, ctx, decode_payload(data)?),
+        TransactionType::ProposeOperation => propose_operation(conn, ctx, decode_payload(data)?),
+        TransactionType::ApproveProposal => approve_proposal(conn, ctx, decode_payload(data)?),
+        TransactionType::ExecuteProposal => execute_proposal(conn, ctx, decode_payload(data)?),
+        TransactionType::DelegateSessionKey => delegate_session_key(conn, ctx, decode_payload(data)?),
+        TransactionType::RevokeSessionKey => revoke_session_key(conn, ctx, decode_payload(data)?),
+        TransactionType::AcceptSessionKey => accept_session_key(conn, ctx, decode_payload(data)?),
+        TransactionType::Pause => pause(conn, ctx, decode_payload(data)?),
+        TransactionType::Unpause => unpause(conn, ctx, decode_payload(data)?),
+        TransactionType::TransferOwnership => transfer_ownership(conn, ctx, decode_payload(data)?),
+        TransactionType::AcceptOwnership => accept_ownership(conn, ctx, decode_payload(data)?),
+        TransactionType::SetDefaultRoyalty => set_default_royalty(conn, ctx, decode_payload(data)?),
+        TransactionType::SetTokenRoyalty => set_token_royalty(conn, ctx, decode_payload(data)?),
+        TransactionType::SetContractURI => set_contract_uri(conn, ctx, decode_payload(data)?),
+        TransactionType::SetContractMetadata => set_contract_metadata(conn, ctx, decode_payload(data)?),
+        TransactionType::FreezeMetadata => freeze_metadata(conn, ctx, decode_payload(data)?),
+        TransactionType::MarkSoulbound => mark_soulbound(conn, ctx, decode_payload(data)?),
+        TransactionType::MintRange => mint_range(conn, ctx, decode_payload(data)?),
+        TransactionType::MintNext => mint_next(conn, ctx, decode_payload(data)?),
+        TransactionType::RedeemVoucher => redeem_voucher(conn, ctx, decode_payload(data)?),
+        TransactionType::SetRelayer => set_relayer(conn, ctx, decode_payload(data)?),
+        TransactionType::SetMintPhase => set_mint_phase(conn, ctx, decode_set_mint_phase(data)?),
+        TransactionType::ClaimAllowlist => claim_allowlist(conn, ctx, decode_payload(data)?),
+        TransactionType::PublicMint => public_mint(conn, ctx, decode_payload(data)?),
+        TransactionType::SetAllowance => set_allowance(conn, ctx, decode_payload(data)?),
+        TransactionType::TransferFrom => transfer_from(conn, ctx, decode_payload(data)?),
+        TransactionType::Permit => permit(conn, ctx, decode_payload(data)?),
+        TransactionType::ForceTransfer => force_transfer(conn, ctx, decode_payload(data)?),
+        TransactionType::DisableClawback => disable_clawback(conn, ctx, decode_payload(data)?),
+        TransactionType::SetBlocked => set_blocked(conn, ctx, decode_payload(data)?),
+        TransactionType::SetAllowedRecipient => set_allowed_recipient(conn, ctx, decode_payload(data)?),
+        TransactionType::SetRecipientAllowlist => set_recipient_allowlist(conn, ctx, decode_payload(data)?),
+        TransactionType::SetUser => set_user(conn, ctx, decode_payload(data)?),
+        TransactionType::SetTokenAttributes => set_token_attributes(conn, ctx, decode_payload(data)?),
+    }
+}
+
+// Operations that change who controls a contract or what its tokens point
+// to. These need multisig approval once a contract sets a threshold above 1
+fn is_sensitive(transaction_type: &TransactionType) -> bool {
+    matches!(
+        transaction_type,
+        TransactionType::AddTokenSigner
+            | TransactionType::RemoveTokenSigner
+            | TransactionType::GrantRole
+            | TransactionType::RevokeRole
+            | TransactionType::SetDefaultTokenURI
+            | TransactionType::SetTokenURIPerId
+            | TransactionType::SetApprovalThreshold
+            | TransactionType::SetContractURI
+            | TransactionType::SetContractMetadata
+            | TransactionType::FreezeMetadata
+            | TransactionType::MarkSoulbound
+            | TransactionType::TransferOwnership
+            | TransactionType::ForceTransfer
+            | TransactionType::DisableClawback
+            | TransactionType::SetBlocked
+            | TransactionType::SetAllowedRecipient
+            | TransactionType::SetRecipientAllowlist
+    )
+}
+
+// Returns the contract a payload targets. CreateToken targets no existing
+// contract
+fn payload_token(transaction_type: &TransactionType, data: &[u8]) -> Result<Option<Address>, DatabaseError> {
+    let token = match transaction_type {
+        TransactionType::CreateToken => return Ok(None),
+        TransactionType::AddTokenSigner => decode_payload::<payload::AddTokenSigner>(data)?.token,
+        TransactionType::RemoveTokenSigner => decode_payload::<payload::RemoveTokenSigner>(data)?.token,
+        TransactionType::GrantRole => decode_payload::<payload::GrantRole>(data)?.token,
+        TransactionType::RevokeRole => decode_payload::<payload::RevokeRole>(data)?.token,
+        TransactionType::SetDefaultTokenURI => decode_payload::<payload::SetDefaultTokenURI>(data)?.token,
+        TransactionType::SetTokenURIPerId => decode_payload::<payload::SetTokenURIPerId>(data)?.token,
+        TransactionType::Mint => decode_payload::<payload::Mint>(data)?.token,
+        TransactionType::Transfer => decode_payload::<payload::Transfer>(data)?.token,
+        TransactionType::Burn => decode_payload::<payload::Burn>(data)?.token,
+        TransactionType::Approve => decode_payload::<payload::Approve>(data)?.token,
+        TransactionType::SetApprovalForAll => decode_payload::<payload::SetApprovalForAll>(data)?.token,
+        TransactionType::SetApprovalThreshold => decode_payload::<payload::SetApprovalThreshold>(data)?.token,
+        TransactionType::ProposeOperation => decode_payload::<payload::ProposeOperation>(data)?.token,
+        TransactionType::ApproveProposal => decode_payload::<payload::ApproveProposal>(data)?.token,
+        TransactionType::ExecuteProposal => decode_payload::<payload::ExecuteProposal>(data)?.token,
+        // Session keys are delegated per signer and may span several contracts
+        TransactionType::DelegateSessionKey
+            | TransactionType::RevokeSessionKey
+            | TransactionType::AcceptSessionKey => return Ok(None),
+        TransactionType::Pause => decode_payload::<payload::Pause>(data)?.token,
+        TransactionType::Unpause => decode_payload::<payload::Unpause>(data)?.token,
+        TransactionType::TransferOwnership => decode_payload::<payload::TransferOwnership>(data)?.token,
+        TransactionType::AcceptOwnership => decode_payload::<payload::AcceptOwnership>(data)?.token,
+        TransactionType::SetDefaultRoyalty => decode_payload::<payload::SetDefaultRoyalty>(data)?.token,
+        TransactionType::SetTokenRoyalty => decode_payload::<payload::SetTokenRoyalty>(data)?.token,
+        TransactionType::SetContractURI => decode_payload::<payload::SetContractURI>(data)?.token,
+        TransactionType::SetContractMetadata => decode_payload::<payload::SetContractMetadata>(data)?.token,
+        TransactionType::FreezeMetadata => decode_payload::<payload::FreezeMetadata>(data)?.token,
+        TransactionType::MarkSoulbound => decode_payload::<payload::MarkSoulbound>(data)?.token,
+        TransactionType::MintRange => decode_payload::<payload::MintRange>(data)?.token,
+        TransactionType::MintNext => decode_payload::<payload::MintNext>(data)?.token,
+        TransactionType::RedeemVoucher => decode_payload::<payload::RedeemVoucher>(data)?.voucher.token,
+        TransactionType::SetRelayer => decode_payload::<payload::SetRelayer>(data)?.token,
+        TransactionType::SetMintPhase => decode_set_mint_phase(data)?.token,
+        TransactionType::ClaimAllowlist => decode_payload::<payload::ClaimAllowlist>(data)?.token,
+        TransactionType::PublicMint => decode_payload::<payload::PublicMint>(data)?.token,
+        TransactionType::SetAllowance => decode_payload::<payload::SetAllowance>(data)?.token,
+        TransactionType::TransferFrom => decode_payload::<payload::TransferFrom>(data)?.token,
+        TransactionType::Permit => decode_payload::<payload::Permit>(data)?.token,
+        TransactionType::ForceTransfer => decode_payload::<payload::ForceTransfer>(data)?.token,
+        TransactionType::DisableClawback => decode_payload::<payload::DisableClawback>(data)?.token,
+        TransactionType::SetBlocked => decode_payload::<payload::SetBlocked>(data)?.token,
+        TransactionType::SetAllowedRecipient => decode_payload::<payload::SetAllowedRecipient>(data)?.token,
+        TransactionType::SetRecipientAllowlist => decode_payload::<payload::SetRecipientAllowlist>(data)?.token,
+        TransactionType::SetUser => decode_payload::<payload::SetUser>(data)?.token,
+        TransactionType::SetTokenAttributes => decode_payload::<payload::SetTokenAttributes>(data)?.token,
+    };
+    Ok(Some(token))
+}
+
+// Returns the amount a payload mints, counted against session key mint
+// limits. Ranges count every id they mint
+fn minted_amount(transaction_type: &TransactionType, data: &[u8]) -> Result<Option<U256>, DatabaseError> {
+    let amount = match transaction_type {
+        TransactionType::Mint => decode_payload::<payload::Mint>(data)?.amount,
+        TransactionType::MintRange => decode_payload::<payload::MintRange>(data)?.quantity,
+        TransactionType::MintNext => decode_payload::<payload::MintNext>(data)?.amount,
+        TransactionType::RedeemVoucher => decode_payload::<payload::RedeemVoucher>(data)?.voucher.amount,
+        TransactionType::ClaimAllowlist => decode_payload::<payload::ClaimAllowlist>(data)?.amount,
+        TransactionType::PublicMint => decode_payload::<payload::PublicMint>(data)?.amount,
+        TransactionType::CreateToken
+            | TransactionType::AddTokenSigner
+            | TransactionType::RemoveTokenSigner
+            | TransactionType::GrantRole
+            | TransactionType::RevokeRole
+            | TransactionType::SetDefaultTokenURI
+            | TransactionType::SetTokenURIPerId
+            | TransactionType::Transfer
+            | TransactionType::Burn
+            | TransactionType::Approve
+            | TransactionType::SetApprovalForAll
+            | TransactionType::SetApprovalThreshold
+            | TransactionType::ProposeOperation
+            | TransactionType::ApproveProposal
+            | TransactionType::ExecuteProposal
+            | TransactionType::DelegateSessionKey
+            | TransactionType::RevokeSessionKey
+            | TransactionType::AcceptSessionKey
+            | TransactionType::Pause
+            | TransactionType::Unpause
+            | TransactionType::TransferOwnership
+            | TransactionType::AcceptOwnership
+            | TransactionType::SetDefaultRoyalty
+            | TransactionType::SetTokenRoyalty
+            | TransactionType::SetContractURI
+            | TransactionType::SetContractMetadata
+            | TransactionType::FreezeMetadata
+            | TransactionType::MarkSoulbound
+            | TransactionType::SetRelayer
+            | TransactionType::SetMintPhase
+            | TransactionType::SetAllowance
+            | TransactionType::TransferFrom
+            | TransactionType::Permit
+            | TransactionType::ForceTransfer
+            | TransactionType::DisableClawback
+            | TransactionType::SetBlocked
+            | TransactionType::SetAllowedRecipient
+            | TransactionType::SetRecipientAllowlist
+            | TransactionType::SetUser
+            | TransactionType::SetTokenAttributes => return Ok(None),
+    };
+    Ok(Some(amount))
+}
+
+// Looks up the contract targeted by a transaction, rejecting the transaction
+// if the contract does not exist
+fn contract_for(conn: &Connection, token: Address) -> Result<Contracts, DatabaseError> {
+    match Contracts::get_by_address(conn, AddressSqlite::from(token)) {
+        Ok(contract) => Ok(contract),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(DatabaseError::UnknownContract(token)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn require_role(
+    conn: &Connection,
+    ctx: &Context,
+    contract: &Contracts,
+    role: Role
+) -> Result<(), DatabaseError> {
+    let account = acting_account(conn, ctx)?;
+    if !ContractSigners::has_role(conn, contract.id, account, role)? {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} does not have the {} role on {}", account, role, contract.address)
+        ));
+    }
+    Ok(())
+}
+
+// The account whose roles and ownership an operation is checked against.
+// While executing a proposal that is its proposer, so approvals from other
+// signers add to the proposer's authority but never lend them a role
+fn acting_account(conn: &Connection, ctx: &Context) -> Result<AddressSqlite, DatabaseError> {
+    match ctx.proposal_id {
+        Some(id) => Ok(Proposals::get_by_id(conn, id)?.proposer),
+        None => Ok(ctx.sender),
+    }
+}
+
+fn require_signer(conn: &Connection, ctx: &Context, contract: &Contracts) -> Result<(), DatabaseError> {
+    if !ContractSigners::is_signer(conn, contract.id, ctx.sender)? {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} is not a signer of {}", ctx.sender, contract.address)
+        ));
+    }
+    Ok(())
+}
+
+// Removing signers must leave enough of them to reach the approval threshold
+fn require_threshold_reachable(conn: &Connection, contract: &Contracts) -> Result<(), DatabaseError> {
+    let signers = ContractSigners::count_signers(conn, contract.id)?;
+    if signers < contract.approval_threshold {
+        return Err(DatabaseError::InvalidTransactionData(format!(
+            "{} would have {} signers, below its approval threshold of {}",
+            contract.address, signers, contract.approval_threshold
+        )));
+    }
+    Ok(())
+}
+
+fn parse_role(role: &str) -> Result<Role, DatabaseError> {
+    role.parse()
+        .map_err(|_| DatabaseError::InvalidTransactionData(format!("unknown role {}", role)))
+}
+
+fn insert_role(
+    conn: &Connection,
+    contract: &Contracts,
+    transaction_id: i32,
+    account: AddressSqlite,
+    role: Role
+) -> Result<(), DatabaseError> {
+    conn.execute(
+        "INSERT INTO contract_signers (contract_id, signer, role, transaction_id) VALUES (?1, ?2, ?3, ?4)",
+        (contract.id, account, role, transaction_id),
+    )?;
+    Ok(())
+}
+
+// A contract without an admin could never be managed again
+fn require_other_admin(conn: &Connection, contract: &Contracts, account: AddressSqlite) -> Result<(), DatabaseError> {
+    if ContractSigners::has_role(conn, contract.id, account, Role::Admin)?
+        && ContractSigners::count_with_role(conn, contract.id, Role::Admin)? == 1
+    {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("cannot remove the last admin of {}", contract.address)
+        ));
+    }
+    Ok(())
+}
+
+// Adding a signer grants them the Minter role, matching what signers could do
+// before roles existed. Further roles are granted with GrantRole
+fn add_token_signer(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::AddTokenSigner
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    let signer = AddressSqlite::from(payload.signer);
+    if ContractSigners::is_signer(conn, contract.id, signer)? {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} is already a signer of {}", signer, contract.address)
+        ));
+    }
+
+    insert_role(conn, &contract, ctx.transaction_id, signer, Role::Minter)
+}
+
+// Removing a signer revokes every role they hold
+fn remove_token_signer(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::RemoveTokenSigner
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    let signer = AddressSqlite::from(payload.signer);
+    if !ContractSigners::is_signer(conn, contract.id, signer)? {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} is not a signer of {}", signer, contract.address)
+        ));
+    }
+    require_other_admin(conn, &contract, signer)?;
+
+    conn.execute(
+        "DELETE FROM contract_signers WHERE contract_id = ?1 AND signer = ?2",
+        (contract.id, signer),
+    )?;
+
+    require_threshold_reachable(conn, &contract)
+}
+
+fn grant_role(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::GrantRole
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    let account = AddressSqlite::from(payload.account);
+    let role = parse_role(&payload.role)?;
+    if ContractSigners::has_role(conn, contract.id, account, role)? {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} already has the {} role on {}", account, role, contract.address)
+        ));
+    }
+
+    insert_role(conn, &contract, ctx.transaction_id, account, role)
+}
+
+fn revoke_role(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::RevokeRole
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    let account = AddressSqlite::from(payload.account);
+    let role = parse_role(&payload.role)?;
+    if !ContractSigners::has_role(conn, contract.id, account, role)? {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} does not have the {} role on {}", account, role, contract.address)
+        ));
+    }
+    if role == Role::Admin {
+        require_other_admin(conn, &contract, account)?;
+    }
+
+    conn.execute(
+        "DELETE FROM contract_signers WHERE contract_id = ?1 AND signer = ?2 AND role = ?3",
+        (contract.id, account, role),
+    )?;
+
+    require_threshold_reachable(conn, &contract)
+}
+
+fn set_default_token_uri(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetDefaultTokenURI
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::UriManager)?;
+    if contract.metadata_frozen {
+        return Err(DatabaseError::MetadataFrozen(format!("token URIs of {}", contract.address)));
+    }
+
+    conn.execute(
+        "UPDATE contracts SET default_token_uri = ?1 WHERE id = ?2",
+        (&payload.uri, contract.id),
+    )?;
+
+    Ok(())
+}
+
+fn set_token_uri_per_id(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetTokenURIPerId
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::UriManager)?;
+    if contract.is_token_metadata_frozen(conn, payload.id)? {
+        return Err(DatabaseError::MetadataFrozen(format!("token {} of {}", payload.id, contract.address)));
+    }
+
+    conn.execute(
+        "INSERT INTO token_uris (contract_id, token_id, uri, transaction_id) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(contract_id, token_id) DO UPDATE SET uri = excluded.uri, transaction_id = excluded.transaction_id",
+        (contract.id, U256Sqlite::from(payload.id), &payload.uri, ctx.transaction_id),
+    )?;
+
+    Ok(())
+}
+
+fn emit_event<E: SolEvent>(
+    conn: &Connection,
+    ctx: &Context,
+    contract: &Contracts,
+    event: &E
+) -> Result<(), DatabaseError> {
+    let log = event.encode_log_data();
+    let topics: Vec<u8> = log.topics().iter().flat_map(|topic| topic.0).collect();
+    let name = E::SIGNATURE.split('(').next().unwrap_or(E::SIGNATURE);
+
+    conn.execute(
+        "INSERT INTO events (transaction_id, contract_id, name, topics, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+        (ctx.transaction_id, contract.id, name, topics, log.data.as_ref()),
+    )?;
+    Ok(())
+}
+
+fn require_not_paused(contract: &Contracts) -> Result<(), DatabaseError> {
+    if contract.paused {
+        return Err(DatabaseError::ContractPaused(contract.address.0));
+    }
+    Ok(())
+}
+
+fn require_transferable(conn: &Connection, contract: &Contracts, id: U256) -> Result<(), DatabaseError> {
+    if contract.is_soulbound(conn, id)? {
+        return Err(DatabaseError::Soulbound(format!("token {} of {}", id, contract.address)));
+    }
+    Ok(())
+}
+
+fn require_not_blocked(conn: &Connection, contract: &Contracts, account: AddressSqlite) -> Result<(), DatabaseError> {
+    if contract.is_blocked(conn, account)? {
+        return Err(DatabaseError::AddressBlocked(format!("{} on {}", account, contract.address)));
+    }
+    Ok(())
+}
+
+fn require_allowed_recipient(conn: &Connection, contract: &Contracts, to: AddressSqlite) -> Result<(), DatabaseError> {
+    require_not_blocked(conn, contract, to)?;
+    if !contract.is_allowed_recipient(conn, to)? {
+        return Err(DatabaseError::RecipientNotAllowed(format!("{} on {}", to, contract.address)));
+    }
+    Ok(())
+}
+
+fn require_valid_amount(amount: U256) -> Result<(), DatabaseError> {
+    if amount.is_zero() {
+        return Err(DatabaseError::InvalidTransactionData("amount must be non-zero".to_string()));
+    }
+    Ok(())
+}
+
+fn require_valid_recipient(to: Address) -> Result<(), DatabaseError> {
+    if to == Address::ZERO {
+        return Err(DatabaseError::InvalidTransactionData("cannot send to the zero address".to_string()));
+    }
+    Ok(())
+}
+
+fn set_balance(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    token_id: U256,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    if amount.is_zero() {
+        conn.execute(
+            "DELETE FROM balances WHERE contract_id = ?1 AND token_id = ?2 AND owner = ?3",
+            (contract_id, U256Sqlite::from(token_id), owner),
+        )?;
+    } else {
+        conn.execute(
+            "INSERT INTO balances (contract_id, token_id, owner, amount) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(contract_id, token_id, owner) DO UPDATE SET amount = excluded.amount",
+            (contract_id, U256Sqlite::from(token_id), owner, U256Sqlite::from(amount)),
+        )?;
+    }
+    Ok(())
+}
+
+fn credit(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    token_id: U256,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    let balance = Balances::balance_of(conn, contract_id, owner, token_id)?;
+    let balance = balance.checked_add(amount)
+        .ok_or_else(|| DatabaseError::InvalidTransactionData("balance overflow".to_string()))?;
+    set_balance(conn, contract_id, owner, token_id, balance)
+}
+
+fn debit(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    token_id: U256,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    split_token_range(conn, contract_id, token_id)?;
+    let balance = Balances::balance_of(conn, contract_id, owner, token_id)?;
+    let balance = balance.checked_sub(amount).ok_or_else(|| DatabaseError::InsufficientBalance(
+        format!("{} holds {} of token {}, needs {}", owner, balance, token_id, amount)
+    ))?;
+    set_balance(conn, contract_id, owner, token_id, balance)
+}
+
+// Moves `token_id` out of the range covering it, if any, and into the
+// balances of the range owner. What is left of the range stays compact
+fn split_token_range(conn: &Connection, contract_id: i32, token_id: U256) -> Result<(), DatabaseError> {
+    let Some(range) = TokenRanges::get_covering(conn, contract_id, token_id)? else {
+        return Ok(());
+    };
+
+    conn.execute(
+        "DELETE FROM token_ranges WHERE contract_id = ?1 AND start_id = ?2",
+        (contract_id, range.start_id),
+    )?;
+    let insert_range = |start_id: U256, end_id: U256| conn.execute(
+        "INSERT INTO token_ranges (contract_id, start_id, end_id, owner, transaction_id)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        (contract_id, U256Sqlite::from(start_id), U256Sqlite::from(end_id), range.owner, range.transaction_id),
+    );
+    if range.start_id.0 < token_id {
+        insert_range(range.start_id.0, token_id - U256::from(1))?;
+    }
+    if token_id < range.end_id.0 {
+        insert_range(token_id + U256::from(1), range.end_id.0)?;
+    }
+
+    credit(conn, contract_id, range.owner, token_id, U256::from(1))
+}
+
+fn is_operator(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    operator: AddressSqlite
+) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT EXISTS(
+            SELECT 1 FROM operator_approvals WHERE contract_id = ? AND owner = ? AND operator = ?
+        )",
+        (contract_id, owner, operator),
+        |row| row.get(0)
+    )
+}
+
+// Holders may always move their own tokens. Anyone else needs either a
+// per-token approval or an operator approval from the holder
+fn require_holder_or_approved(
+    conn: &Connection,
+    contract: &Contracts,
+    sender: AddressSqlite,
+    owner: AddressSqlite,
+    token_id: U256
+) -> Result<(), DatabaseError> {
+    if sender == owner || is_operator(conn, contract.id, owner, sender)? {
+        return Ok(());
+    }
+
+    let approved: bool = conn.query_row(
+        "SELECT EXISTS(
+            SELECT 1 FROM token_approvals
+            WHERE contract_id = ? AND token_id = ? AND owner = ? AND spender = ?
+        )",
+        (contract.id, U256Sqlite::from(token_id), owner, sender),
+        |row| row.get(0)
+    )?;
+    if !approved {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} is not approved to move token {} of {}", sender, token_id, owner)
+        ));
+    }
+    Ok(())
+}
+
+fn clear_token_approval(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    token_id: U256
+) -> Result<(), DatabaseError> {
+    conn.execute(
+        "DELETE FROM token_approvals WHERE contract_id = ?1 AND token_id = ?2 AND owner = ?3",
+        (contract_id, U256Sqlite::from(token_id), owner),
+    )?;
+    Ok(())
+}
+
+// A new holder starts without a user, as in ERC-4907
+fn clear_token_user(conn: &Connection, ctx: &Context, contract: &Contracts, token_id: U256) -> Result<(), DatabaseError> {
+    let deleted = conn.execute(
+        "DELETE FROM token_users WHERE contract_id = ?1 AND token_id = ?2",
+        (contract.id, U256Sqlite::from(token_id)),
+    )?;
+    if deleted == 0 {
+        return Ok(());
+    }
+
+    emit_event(conn, ctx, contract, &events::UpdateUser { tokenId: token_id, user: Address::ZERO, expires: 0 })
+}
+
+// SetMintPhase gained uniqueIds after it was first used. Payloads encoded
+// without it decode with the field unset, as in decode_create_token
+fn decode_set_mint_phase(data: &[u8]) -> Result<payload::SetMintPhase, DatabaseError> {
+    decode_payload::<payload::SetMintPhase>(data)
+        .or_else(|e| decode_payload::<payload::v1::SetMintPhase>(data).map(Into::into).map_err(|_| e))
+}
+
+// The contract itself is created by create_contract_trigger. This applies
+// the supply caps and metadata declared in the payload
+// CreateToken gained fields after it was first used. Payloads are decoded as
+// the latest version first, then as earlier ones, so data encoded for an
+// earlier version keeps working with the added fields at their defaults
+fn decode_create_token(data: &[u8]) -> Result<payload::CreateToken, DatabaseError> {
+    if data.is_empty() {
+        return Ok(payload::CreateToken::default());
+    }
+    decode_payload::<payload::CreateToken>(data)
+        .or_else(|e| decode_payload::<payload::v2::CreateToken>(data).map(Into::into).map_err(|_| e))
+        .or_else(|e| decode_payload::<payload::v1::CreateToken>(data).map(Into::into).map_err(|_| e))
+}
+
+fn create_token(conn: &Connection, ctx: &Context, data: &[u8]) -> Result<(), DatabaseError> {
+    let payload = decode_create_token(data)?;
+    let cap = |amount: U256| (!amount.is_zero()).then_some(U256Sqlite::from(amount));
+
+    conn.execute(
+        "UPDATE contracts SET
+            max_supply = ?1, max_per_address = ?2, name = ?3, permit_name = ?3, symbol = ?4, soulbound = ?5
+        WHERE transaction_id = ?6",
+        (
+            cap(payload.maxSupply),
+            cap(payload.maxPerAddress),
+            &payload.name,
+            &payload.symbol,
+            payload.soulbound,
+            ctx.transaction_id,
+        ),
+    )?;
+
+    let contract = Contracts::get_by_transaction_id(conn, ctx.transaction_id)?;
+    record_contract_metadata(conn, ctx, contract.id)
+}
+
+// Counts a mint against the contract's supply caps, rejecting it if either
+// cap would be exceeded. Every path that mints goes through here
+fn record_mint(
+    conn: &Connection,
+    contract: &Contracts,
+    recipient: AddressSqlite,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    let total_minted = contract.total_minted.0.checked_add(amount)
+        .ok_or_else(|| DatabaseError::MintLimitExceeded("total supply overflow".to_string()))?;
+    if let Some(max_supply) = contract.max_supply {
+        if total_minted > max_supply.0 {
+            return Err(DatabaseError::MintLimitExceeded(format!(
+                "{} has {} of its max supply of {} left",
+                contract.address, contract.remaining_supply().unwrap_or_default(), max_supply
+            )));
+        }
+    }
+
+    let minted_to = contract.minted_to(conn, recipient)?.saturating_add(amount);
+    if let Some(max_per_address) = contract.max_per_address {
+        if minted_to > max_per_address.0 {
+            return Err(DatabaseError::MintLimitExceeded(format!(
+                "{} can be minted at most {} by {}", recipient, max_per_address, contract.address
+            )));
+        }
+    }
+
+    conn.execute(
+        "UPDATE contracts SET total_minted = ?1 WHERE id = ?2",
+        (U256Sqlite::from(total_minted), contract.id),
+    )?;
+    conn.execute(
+        "INSERT INTO mint_counts (contract_id, recipient, amount) VALUES (?1, ?2, ?3)
+        ON CONFLICT(contract_id, recipient) DO UPDATE SET amount = excluded.amount",
+        (contract.id, recipient, U256Sqlite::from(minted_to)),
+    )?;
+
+    Ok(())
+}
+
+fn mint(conn: &Connection, ctx: &Context, payload: payload::Mint) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Minter)?;
+    mint_to(conn, &contract, payload.to, payload.id, payload.amount)
+}
+
+// Mints once the caller has established that the mint is authorized
+fn mint_to(
+    conn: &Connection,
+    contract: &Contracts,
+    to: Address,
+    id: U256,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    require_not_paused(contract)?;
+    require_valid_recipient(to)?;
+    require_valid_amount(amount)?;
+
+    // Ids allocated to a range are ERC-721 tokens that already exist
+    if TokenRanges::get_covering(conn, contract.id, id)?.is_some() {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("token {} of {} was already minted", id, contract.address)
+        ));
+    }
+
+    let to = AddressSqlite::from(to);
+    require_allowed_recipient(conn, contract, to)?;
+    record_mint(conn, contract, to, amount)?;
+    credit(conn, contract.id, to, id, amount)
+}
+
+// EIP-712 domain that typed data is signed under. The verifying contract is
+// the MintVM contract the data targets, and is left out for typed data that
+// targets no single contract, such as CreateToken
+fn eip712_domain(chain_id: u64, token: Option<Address>) -> Eip712Domain {
+    Eip712Domain::new(
+        Some(EIP712_NAME.into()),
+        Some(EIP712_VERSION.into()),
+        Some(U256::from(chain_id)),
+        token,
+        None,
+    )
+}
+
+fn recover_hash_signer(hash: &B256, signature: &[u8]) -> Result<Address, DatabaseError> {
+    let signature = PrimitiveSignature::try_from(signature)
+        .map_err(|e| DatabaseError::InvalidSignature(e.to_string()))?;
+    signature.recover_address_from_prehash(hash)
+        .map_err(|e| DatabaseError::InvalidSignature(e.to_string()))
+}
+
+// Recovers the address that signed an EIP-712 struct for `token`
+fn recover_signer<T: SolStruct>(
+    conn: &Connection,
+    token: Address,
+    data: &T,
+    signature: &[u8]
+) -> Result<Address, DatabaseError> {
+    let hash = data.eip712_signing_hash(&eip712_domain(chain_id(conn)?, Some(token)));
+    recover_hash_signer(&hash, signature)
+}
+
+// The voucher's signer must be a minter of the contract at redemption time,
+// so revoking a minter also invalidates the vouchers it signed
+fn redeem_voucher(conn: &Connection, ctx: &Context, payload: payload::RedeemVoucher) -> Result<(), DatabaseError> {
+    let voucher = payload.voucher;
+    let contract = contract_for(conn, voucher.token)?;
+    let signer = AddressSqlite::from(recover_signer(conn, voucher.token, &voucher, &payload.signature)?);
+    if !ContractSigners::has_role(conn, contract.id, signer, Role::Minter)? {
+        return Err(DatabaseError::Unauthorized(
+            format!("voucher signer {} does not have the {} role on {}", signer, Role::Minter, contract.address)
+        ));
+    }
+    if ctx.has_reached(saturating_time(voucher.expiresAt)) {
+        return Err(DatabaseError::VoucherExpired(voucher.nonce));
+    }
+
+    let to = if voucher.to == Address::ZERO { ctx.sender.0 } else { voucher.to };
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO redeemed_vouchers (contract_id, nonce, signer, recipient, transaction_id)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        (contract.id, U256Sqlite::from(voucher.nonce), signer, AddressSqlite::from(to), ctx.transaction_id),
+    )?;
+    if inserted == 0 {
+        return Err(DatabaseError::VoucherRedeemed(voucher.nonce));
+    }
+
+    mint_to(conn, &contract, to, voucher.id, voucher.amount)
+}
+
+// Allocation happens inside the SQL transaction of insert_transaction, so
+// concurrent senders can never be handed the same id
+fn mint_next(conn: &Connection, ctx: &Context, payload: payload::MintNext) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    let id = contract.next_unminted_id(conn)?;
+
+    conn.execute(
+        "UPDATE contracts SET next_token_id = ?1 WHERE id = ?2",
+        (U256Sqlite::from(id.saturating_add(U256::from(1))), contract.id),
+    )?;
+    conn.execute(
+        "UPDATE receipts SET token_id = ?1 WHERE transaction_id = ?2",
+        (U256Sqlite::from(id), ctx.transaction_id),
+    )?;
+
+    mint(conn, ctx, payload::Mint { token: payload.token, to: payload.to, id, amount: payload.amount })
+}
+
+// Mints a whole range of ERC-721 ids with a single row, however large the
+// range. None of the ids may have been minted before
+fn mint_range(conn: &Connection, ctx: &Context, payload: payload::MintRange) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Minter)?;
+    require_not_paused(&contract)?;
+    require_valid_recipient(payload.to)?;
+    require_valid_amount(payload.quantity)?;
+
+    let end_id = (payload.quantity - U256::from(1)).checked_add(payload.startId)
+        .ok_or_else(|| DatabaseError::InvalidTransactionData("token range overflows".to_string()))?;
+    require_unminted(conn, &contract, payload.startId, end_id)?;
+
+    let to = AddressSqlite::from(payload.to);
+    require_allowed_recipient(conn, &contract, to)?;
+    record_mint(conn, &contract, to, payload.quantity)?;
+    conn.execute(
+        "INSERT INTO token_ranges (contract_id, start_id, end_id, owner, transaction_id)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        (contract.id, U256Sqlite::from(payload.startId), U256Sqlite::from(end_id), to, ctx.transaction_id),
+    )?;
+
+    Ok(())
+}
+
+// Rejects ids from `start_id` through `end_id` that have a balance or are
+// covered by a range
+fn require_unminted(conn: &Connection, contract: &Contracts, start_id: U256, end_id: U256) -> Result<(), DatabaseError> {
+    let already_minted = |id: U256| DatabaseError::InvalidTransactionData(
+        format!("token {} of {} was already minted", id, contract.address)
+    );
+    // A range overlapping this one either covers its first id or starts
+    // within it
+    if TokenRanges::get_covering(conn, contract.id, start_id)?.is_some() {
+        return Err(already_minted(start_id));
+    }
+    let starting_inside: Option<U256Sqlite> = conn.query_row(
+        "SELECT start_id FROM token_ranges WHERE contract_id = ? AND start_id BETWEEN ? AND ? LIMIT 1",
+        (contract.id, U256Sqlite::from(start_id), U256Sqlite::from(end_id)),
+        |row| row.get(0)
+    ).optional()?;
+    if let Some(start_id) = starting_inside {
+        return Err(already_minted(start_id.0));
+    }
+    let minted: Option<U256Sqlite> = conn.query_row(
+        "SELECT token_id FROM balances WHERE contract_id = ? AND token_id BETWEEN ? AND ? LIMIT 1",
+        (contract.id, U256Sqlite::from(start_id), U256Sqlite::from(end_id)),
+        |row| row.get(0)
+    ).optional()?;
+    if let Some(id) = minted {
+        return Err(already_minted(id.0));
+    }
+
+    Ok(())
+}
+
+fn transfer(conn: &Connection, ctx: &Context, payload: payload::Transfer) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    require_transferable(conn, &contract, payload.id)?;
+    let from = AddressSqlite::from(payload.from);
+    require_holder_or_approved(conn, &contract, ctx.sender, from, payload.id)?;
+    require_valid_recipient(payload.to)?;
+    require_valid_amount(payload.amount)?;
+    let to = AddressSqlite::from(payload.to);
+    require_can_transfer(conn, &contract, ctx.sender, from, to)?;
+
+    debit(conn, contract.id, from, payload.id, payload.amount)?;
+    credit(conn, contract.id, to, payload.id, payload.amount)?;
+    clear_token_approval(conn, contract.id, from, payload.id)?;
+    clear_token_user(conn, ctx, &contract, payload.id)
+}
+
+// Neither the holder nor whoever moves the tokens for them may be blocked
+fn require_can_transfer(
+    conn: &Connection,
+    contract: &Contracts,
+    sender: AddressSqlite,
+    from: AddressSqlite,
+    to: AddressSqlite
+) -> Result<(), DatabaseError> {
+    require_not_blocked(conn, contract, sender)?;
+    require_not_blocked(conn, contract, from)?;
+    require_allowed_recipient(conn, contract, to)
+}
+
+// Soulbound tokens cannot be approved, so besides their holder only an
+// admin of the contract can burn them, e.g. to revoke a badge
+fn burn(conn: &Connection, ctx: &Context, payload: payload::Burn) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    let from = AddressSqlite::from(payload.from);
+    if ctx.sender != from && contract.is_soulbound(conn, payload.id)? {
+        require_role(conn, ctx, &contract, Role::Admin)?;
+    } else {
+        require_holder_or_approved(conn, &contract, ctx.sender, from, payload.id)?;
+    }
+    require_valid_amount(payload.amount)?;
+
+    debit(conn, contract.id, from, payload.id, payload.amount)?;
+    if contract.owner_of(conn, payload.id)?.is_none() {
+        conn.execute(
+            "INSERT OR IGNORE INTO burned_tokens (contract_id, token_id, transaction_id) VALUES (?1, ?2, ?3)",
+            (contract.id, U256Sqlite::from(payload.id), ctx.transaction_id),
+        )?;
+    }
+    clear_token_approval(conn, contract.id, from, payload.id)?;
+    clear_token_user(conn, ctx, &contract, payload.id)
+}
+
+// The holder, or anyone approved to move the token for them, can lend it
+// out. Setting a new user replaces the previous one, even before it expires
+fn set_user(conn: &Connection, ctx: &Context, payload: payload::SetUser) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    // ERC-4907 rents out a whole item, so ids held in editions or by several
+    // holders have no single owner to lend them
+    let owner = contract.nft_owner_of(conn, payload.id)?.ok_or_else(|| DatabaseError::InvalidTransactionData(
+        format!("token {} of {} is not held as a single item", payload.id, contract.address)
+    ))?;
+    require_holder_or_approved(conn, &contract, ctx.sender, owner, payload.id)?;
+
+    let user = AddressSqlite::from(payload.user);
+    if payload.user == Address::ZERO {
+        conn.execute(
+            "DELETE FROM token_users WHERE contract_id = ?1 AND token_id = ?2",
+            (contract.id, U256Sqlite::from(payload.id)),
+        )?;
+    } else {
+        require_not_blocked(conn, &contract, user)?;
+        let expires = saturating_time(payload.expires);
+        conn.execute(
+            "INSERT INTO token_users (contract_id, token_id, user, expires, transaction_id)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(contract_id, token_id) DO UPDATE SET
+                user = excluded.user, expires = excluded.expires, transaction_id = excluded.transaction_id",
+            (contract.id, U256Sqlite::from(payload.id), user, expires, ctx.transaction_id),
+        )?;
+    }
+
+    emit_event(conn, ctx, &contract, &events::UpdateUser {
+        tokenId: payload.id,
+        user: payload.user,
+        expires: payload.expires,
+    })
+}
+
+// Attributes are written by the game's signers, not by holders, so players
+// cannot level up their own items
+fn set_token_attributes(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetTokenAttributes
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_signer(conn, ctx, &contract)?;
+    if contract.owner_of(conn, payload.id)?.is_none() {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("token {} of {} does not exist", payload.id, contract.address)
+        ));
+    }
+    if payload.attributes.is_empty() {
+        return Err(DatabaseError::InvalidTransactionData("no attributes to set".to_string()));
+    }
+
+    let token_id = U256Sqlite::from(payload.id);
+    for attribute in &payload.attributes {
+        if attribute.key.is_empty() {
+            return Err(DatabaseError::InvalidTransactionData("attribute key must be non-empty".to_string()));
+        }
+
+        let value = (!attribute.value.is_empty()).then_some(attribute.value.as_str());
+        let changed = match value {
+            Some(value) => {
+                let number = value.parse::<f64>().ok().filter(|number| number.is_finite());
+                conn.execute(
+                    "INSERT INTO token_attributes (contract_id, token_id, key, value, number, transaction_id)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT(contract_id, token_id, key) DO UPDATE SET
+                        value = excluded.value, number = excluded.number, transaction_id = excluded.transaction_id",
+                    (contract.id, token_id, &attribute.key, value, number, ctx.transaction_id),
+                )?
+            }
+            None => conn.execute(
+                "DELETE FROM token_attributes WHERE contract_id = ?1 AND token_id = ?2 AND key = ?3",
+                (contract.id, token_id, &attribute.key),
+            )?,
+        };
+        // Removing an attribute the token does not have changes nothing, so
+        // there is nothing to record
+        if changed == 0 {
+            continue;
+        }
+        conn.execute(
+            "INSERT INTO token_attribute_history (contract_id, token_id, key, value, transaction_id)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            (contract.id, token_id, &attribute.key, value, ctx.transaction_id),
+        )?;
+    }
+
+    Ok(())
+}
+
+// ERC-4907 userOf. Expiry is checked against the latest block, or against
+// GENESIS_TIMESTAMP before the first block
+fn user_of(conn: &Connection, token: Address, id: U256) -> Result<Address, DatabaseError> {
+    let contract = contract_for(conn, token)?;
+    let Some(token_user) = TokenUsers::get(conn, contract.id, id)? else {
+        return Ok(Address::ZERO);
+    };
+
+    let block_time = Blocks::get_latest(conn)?.map_or(GENESIS_TIMESTAMP, |block| block.timestamp);
+    if token_user.is_active(block_time) {
+        Ok(token_user.user.0)
+    } else {
+        Ok(Address::ZERO)
+    }
+}
+
+// Only the holder or one of their operators can approve a spender, and only
+// for a token the holder actually owns
+fn approve(conn: &Connection, ctx: &Context, payload: payload::Approve) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    require_transferable(conn, &contract, payload.id)?;
+    let owner = AddressSqlite::from(payload.owner);
+    if ctx.sender != owner && !is_operator(conn, contract.id, owner, ctx.sender)? {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} cannot approve on behalf of {}", ctx.sender, owner)
+        ));
+    }
+    if Balances::balance_of(conn, contract.id, owner, payload.id)?.is_zero() {
+        return Err(DatabaseError::InsufficientBalance(
+            format!("{} does not hold token {}", owner, payload.id)
+        ));
+    }
+
+    // Approving the zero address clears the approval, as in ERC-721
+    if payload.spender == Address::ZERO {
+        return clear_token_approval(conn, contract.id, owner, payload.id);
+    }
+    // Clearing approvals is always possible, granting them is not for
+    // blocked addresses
+    let spender = AddressSqlite::from(payload.spender);
+    require_not_blocked(conn, &contract, ctx.sender)?;
+    require_not_blocked(conn, &contract, owner)?;
+    require_not_blocked(conn, &contract, spender)?;
+    conn.execute(
+        "INSERT INTO token_approvals (contract_id, token_id, owner, spender) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(contract_id, token_id, owner) DO UPDATE SET spender = excluded.spender",
+        (contract.id, U256Sqlite::from(payload.id), owner, spender),
+    )?;
+
+    Ok(())
+}
+
+fn set_approval_for_all(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetApprovalForAll
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    // Ids marked soulbound individually are guarded by transfer itself, so
+    // operators are only refused on contracts that are soulbound as a whole
+    if contract.soulbound {
+        return Err(DatabaseError::Soulbound(format!("tokens of {}", contract.address)));
+    }
+    let operator = AddressSqlite::from(payload.operator);
+    if operator == ctx.sender {
+        return Err(DatabaseError::InvalidTransactionData("cannot approve self as operator".to_string()));
+    }
+
+    if payload.approved {
+        require_not_blocked(conn, &contract, ctx.sender)?;
+        require_not_blocked(conn, &contract, operator)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO operator_approvals (contract_id, owner, operator) VALUES (?1, ?2, ?3)",
+            (contract.id, ctx.sender, operator),
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM operator_approvals WHERE contract_id = ?1 AND owner = ?2 AND operator = ?3",
+            (contract.id, ctx.sender, operator),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_allowance(
+    conn: &Connection,
+    contract_id: i32,
+    owner: AddressSqlite,
+    spender: AddressSqlite,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    if amount.is_zero() {
+        conn.execute(
+            "DELETE FROM allowances WHERE contract_id = ?1 AND owner = ?2 AND spender = ?3",
+            (contract_id, owner, spender),
+        )?;
+    } else {
+        conn.execute(
+            "INSERT INTO allowances (contract_id, owner, spender, amount) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(contract_id, owner, spender) DO UPDATE SET amount = excluded.amount",
+            (contract_id, owner, spender, U256Sqlite::from(amount)),
+        )?;
+    }
+    Ok(())
+}
+
+// As in ERC-20, the allowance is overwritten rather than added to
+fn set_allowance(conn: &Connection, ctx: &Context, payload: payload::SetAllowance) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    require_transferable(conn, &contract, ERC20_TOKEN_ID)?;
+    if payload.spender == Address::ZERO {
+        return Err(DatabaseError::InvalidTransactionData("cannot approve the zero address".to_string()));
+    }
+    let spender = AddressSqlite::from(payload.spender);
+    if !payload.amount.is_zero() {
+        require_not_blocked(conn, &contract, ctx.sender)?;
+        require_not_blocked(conn, &contract, spender)?;
+    }
+
+    write_allowance(conn, contract.id, ctx.sender, spender, payload.amount)
+}
+
+// Spending an infinite allowance leaves it untouched, as OpenZeppelin's
+// ERC20 does
+fn spend_allowance(
+    conn: &Connection,
+    contract: &Contracts,
+    owner: AddressSqlite,
+    spender: AddressSqlite,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    let allowance = Allowances::allowance(conn, contract.id, owner, spender)?;
+    if allowance == U256::MAX {
+        return Ok(());
+    }
+    let allowance = allowance.checked_sub(amount).ok_or_else(|| DatabaseError::InsufficientAllowance(
+        format!("{} may spend {} of {}'s balance, needs {}", spender, allowance, owner, amount)
+    ))?;
+    write_allowance(conn, contract.id, owner, spender, allowance)
+}
+
+// EIP-2612 domain of a contract. Like OpenZeppelin's ERC20Permit it is named
+// after the token as created and fixed from then on, so a later
+// SetContractMetadata does not invalidate permits. Dapps read it through
+// DOMAIN_SEPARATOR() rather than rebuilding it from name()
+fn permit_domain(conn: &Connection, contract: &Contracts) -> Result<Eip712Domain, DatabaseError> {
+    Ok(Eip712Domain::new(
+        Some(contract.permit_name.clone().into()),
+        Some(EIP712_VERSION.into()),
+        Some(U256::from(chain_id(conn)?)),
+        Some(contract.address.0),
+        None,
+    ))
+}
+
+fn permit_nonce(conn: &Connection, contract_id: i32, owner: AddressSqlite) -> Result<u64, rusqlite::Error> {
+    let nonce: Option<i64> = conn.query_row(
+        "SELECT nonce FROM permit_nonces WHERE contract_id = ? AND owner = ?",
+        (contract_id, owner),
+        |row| row.get(0)
+    ).optional()?;
+
+    Ok(nonce.map_or(0, |nonce| nonce as u64))
+}
+
+fn permit(conn: &Connection, ctx: &Context, payload: payload::Permit) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    // As in EIP-2612 the permit is valid through the deadline itself
+    if ctx.has_reached(saturating_time(payload.deadline).saturating_add(1)) {
+        return Err(DatabaseError::PermitExpired(payload.deadline));
+    }
+
+    let owner = AddressSqlite::from(payload.owner);
+    let nonce = permit_nonce(conn, contract.id, owner)?;
+    let permit = typed_data::Permit {
+        owner: payload.owner,
+        spender: payload.spender,
+        value: payload.value,
+        nonce: U256::from(nonce),
+        deadline: payload.deadline,
+    };
+    // v is 27 or 28 in EIP-2612 signatures
+    let y_parity = match payload.v {
+        27 | 28 => payload.v == 28,
+        v => return Err(DatabaseError::InvalidSignature(format!("invalid v value {}", v))),
+    };
+    let signature = PrimitiveSignature::new(payload.r.into(), payload.s.into(), y_parity);
+    let signer = signature.recover_address_from_prehash(&permit.eip712_signing_hash(&permit_domain(conn, &contract)?))
+        .map_err(|e| DatabaseError::InvalidSignature(e.to_string()))?;
+    if payload.owner == Address::ZERO || signer != payload.owner {
+        return Err(DatabaseError::InvalidSignature(
+            format!("permit for {} was signed by {}", payload.owner, signer)
+        ));
+    }
+
+    conn.execute(
+        "INSERT INTO permit_nonces (contract_id, owner, nonce) VALUES (?1, ?2, 1)
+        ON CONFLICT(contract_id, owner) DO UPDATE SET nonce = nonce + 1",
+        (contract.id, owner),
+    )?;
+    set_allowance(conn, &Context { sender: owner, ..*ctx }, payload::SetAllowance {
+        token: payload.token,
+        spender: payload.spender,
+        amount: payload.value,
+    })
+}
+
+fn transfer_from(conn: &Connection, ctx: &Context, payload: payload::TransferFrom) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_not_paused(&contract)?;
+    require_transferable(conn, &contract, ERC20_TOKEN_ID)?;
+    require_valid_recipient(payload.to)?;
+    require_valid_amount(payload.amount)?;
+
+    let from = AddressSqlite::from(payload.from);
+    let to = AddressSqlite::from(payload.to);
+    require_can_transfer(conn, &contract, ctx.sender, from, to)?;
+    spend_allowance(conn, &contract, from, ctx.sender, payload.amount)?;
+    debit(conn, contract.id, from, ERC20_TOKEN_ID, payload.amount)?;
+    credit(conn, contract.id, to, ERC20_TOKEN_ID, payload.amount)
+}
+
+fn set_approval_threshold(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetApprovalThreshold
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    let threshold = payload.threshold as i64;
+    if threshold < 1 {
+        return Err(DatabaseError::InvalidTransactionData("threshold must be at least 1".to_string()));
+    }
+
+    conn.execute(
+        "UPDATE contracts SET approval_threshold = ?1 WHERE id = ?2",
+        (threshold, contract.id),
+    )?;
+
+    let contract = Contracts::get_by_id(conn, contract.id)?;
+    require_threshold_reachable(conn, &contract)
+}
+
+// Loads a proposal made against `contract` that can still be acted on
+fn pending_proposal(
+    conn: &Connection,
+    ctx: &Context,
+    contract: &Contracts,
+    proposal_id: u64
+) -> Result<Proposals, DatabaseError> {
+    let proposal = match i32::try_from(proposal_id) {
+        Ok(id) => Proposals::get_by_id(conn, id).optional()?,
+        Err(_) => None,
+    };
+    let proposal = proposal
+        .filter(|proposal| proposal.contract_id == contract.id)
+        .ok_or_else(|| DatabaseError::InvalidTransactionData(
+            format!("unknown proposal {} on {}", proposal_id, contract.address)
+        ))?;
+
+    if proposal.executed_transaction_id.is_some() {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("proposal {} was already executed", proposal.id)
+        ));
+    }
+    if proposal.is_expired(ctx) {
+        return Err(DatabaseError::ProposalExpired(proposal.id));
+    }
+    Ok(proposal)
+}
+
+fn insert_proposal_approval(conn: &Connection, ctx: &Context, proposal_id: i32) -> Result<(), DatabaseError> {
+    conn.execute(
+        "INSERT INTO proposal_approvals (proposal_id, signer, transaction_id) VALUES (?1, ?2, ?3)",
+        (proposal_id, ctx.sender, ctx.transaction_id),
+    )?;
+    Ok(())
+}
+
+// Any signer can propose a sensitive operation on a contract with a
+// threshold above 1. The proposal counts as approved by the proposer, and
+// runs with the proposer's roles once executed
+fn propose_operation(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::ProposeOperation
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_signer(conn, ctx, &contract)?;
+    // Below that the proposer's own approval would be enough to execute it
+    if contract.approval_threshold <= 1 {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} does not require multisig approval", contract.address)
+        ));
+    }
+
+    let transaction_type: TransactionType = payload.transactionType.parse()
+        .map_err(|_| DatabaseError::InvalidTransactionType(payload.transactionType.clone()))?;
+    if !is_sensitive(&transaction_type) {
+        return Err(DatabaseError::InvalidTransactionType(
+            format!("{} does not need multisig approval", transaction_type)
+        ));
+    }
+    // The operation must target the contract whose signers approve it
+    if payload_token(&transaction_type, &payload.data)? != Some(payload.token) {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("proposed operation does not target {}", contract.address)
+        ));
+    }
+    let expires_at = saturating_time(payload.expiresAt);
+    if ctx.has_reached(expires_at) {
+        return Err(DatabaseError::InvalidTransactionData("proposal is already expired".to_string()));
+    }
+
+    conn.execute(
+        "INSERT INTO proposals (contract_id, proposer, transaction_type, data, expires_at, transaction_id)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            contract.id,
+            ctx.sender,
+            &transaction_type,
+            payload.data.as_ref(),
+            expires_at,
+            ctx.transaction_id,
+        ),
+    )?;
+    let proposal_id = conn.last_insert_rowid() as i32;
+
+    insert_proposal_approval(conn, ctx, proposal_id)
+}
+
+fn approve_proposal(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::ApproveProposal
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_signer(conn, ctx, &contract)?;
+    let proposal = pending_proposal(conn, ctx, &contract, payload.proposalId)?;
+
+    let approved: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM proposal_approvals WHERE proposal_id = ? AND signer = ?)",
+        (proposal.id, ctx.sender),
+        |row| row.get(0)
+    )?;
+    if approved {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} already approved proposal {}", ctx.sender, proposal.id)
+        ));
+    }
+
+    insert_proposal_approval(conn, ctx, proposal.id)
+}
+
+// Any signer can execute a proposal once enough signers have approved it.
+// The operation is applied as part of the executing transaction
+fn execute_proposal(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::ExecuteProposal
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_signer(conn, ctx, &contract)?;
+    let proposal = pending_proposal(conn, ctx, &contract, payload.proposalId)?;
+
+    let approvals = proposal.count_approvals(conn)?;
+    if approvals < contract.approval_threshold {
+        return Err(DatabaseError::Unauthorized(format!(
+            "proposal {} has {} of {} required approvals",
+            proposal.id, approvals, contract.approval_threshold
+        )));
+    }
+
+    conn.execute(
+        "UPDATE proposals SET executed_transaction_id = ?1 WHERE id = ?2",
+        (ctx.transaction_id, proposal.id),
+    )?;
+
+    let proposal_ctx = Context { proposal_id: Some(proposal.id), ..*ctx };
+    process_transaction(conn, &proposal_ctx, &proposal.transaction_type, &proposal.data)
+}
+
+// While an address is an active session key, its transactions must fall
+// within the scope of its latest delegation and are applied with the
+// delegating signer as the sender. Before the key accepts the delegation and
+// once it expires or is revoked, the address sends as itself again
+fn resolve_session_key(
+    conn: &Connection,
+    ctx: Context,
+    transaction_type: &TransactionType,
+    data: &[u8]
+) -> Result<Context, DatabaseError> {
+    let session = match SessionKeys::get_latest_by_session_key(conn, ctx.sender)? {
+        Some(session) if session.is_active(&ctx) => session,
+        _ => return Ok(ctx),
+    };
+
+    let contract = match payload_token(transaction_type, data)? {
+        Some(token) => contract_for(conn, token)?,
+        None => return Err(DatabaseError::Unauthorized(
+            format!("session key {} cannot send {}", ctx.sender, transaction_type)
+        )),
+    };
+    if !session.allows(conn, contract.id, transaction_type)? {
+        return Err(DatabaseError::Unauthorized(format!(
+            "session key {} is not allowed to send {} to {}",
+            ctx.sender, transaction_type, contract.address
+        )));
+    }
+
+    if let Some(minted) = minted_amount(transaction_type, data)? {
+        let mints = session.mints.0.checked_add(minted)
+            .filter(|mints| *mints <= session.max_mints.0)
+            .ok_or_else(|| DatabaseError::Unauthorized(
+                format!("session key {} would exceed its mint limit of {}", ctx.sender, session.max_mints)
+            ))?;
+        conn.execute(
+            "UPDATE session_keys SET mints = ?1 WHERE id = ?2",
+            (U256Sqlite::from(mints), session.id),
+        )?;
+    }
+
+    Ok(Context { sender: session.delegator, ..ctx })
+}
+
+// A signer can delegate a session key for contracts they are a signer of.
+// The key never holds more authority than the signer, since it acts as them.
+// The delegation only takes effect once the key accepts it, so no address
+// can be turned into someone's session key without its consent
+fn delegate_session_key(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::DelegateSessionKey
+) -> Result<(), DatabaseError> {
+    let session_key = AddressSqlite::from(payload.sessionKey);
+    if session_key == ctx.sender {
+        return Err(DatabaseError::InvalidTransactionData("cannot delegate to self".to_string()));
+    }
+    let expires_at = saturating_time(payload.expiresAt);
+    if ctx.has_reached(expires_at) {
+        return Err(DatabaseError::InvalidTransactionData("session key is already expired".to_string()));
+    }
+    if let Some(session) = SessionKeys::get_latest_by_session_key(conn, session_key)? {
+        if session.is_active(ctx) {
+            return Err(DatabaseError::InvalidTransactionData(
+                format!("session key {} is already delegated", session_key)
+            ));
+        }
+    }
+
+    let contracts = payload.tokens.iter()
+        .map(|token| {
+            let contract = contract_for(conn, *token)?;
+            require_signer(conn, ctx, &contract)?;
+            Ok(contract)
+        })
+        .collect::<Result<Vec<_>, DatabaseError>>()?;
+    let transaction_types = payload.transactionTypes.iter()
+        .map(|name| name.parse::<TransactionType>()
+            .map_err(|_| DatabaseError::InvalidTransactionType(name.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    conn.execute(
+        "INSERT INTO session_keys (session_key, delegator, max_mints, mints, expires_at, transaction_id)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        (
+            session_key,
+            ctx.sender,
+            U256Sqlite::from(payload.maxMints),
+            U256Sqlite::from(U256::ZERO),
+            expires_at,
+            ctx.transaction_id,
+        ),
+    )?;
+    let session_key_id = conn.last_insert_rowid();
+
+    for contract in &contracts {
+        conn.execute(
+            "INSERT OR IGNORE INTO session_key_contracts (session_key_id, contract_id) VALUES (?1, ?2)",
+            (session_key_id, contract.id),
+        )?;
+    }
+    for transaction_type in &transaction_types {
+        conn.execute(
+            "INSERT OR IGNORE INTO session_key_types (session_key_id, transaction_type) VALUES (?1, ?2)",
+            (session_key_id, transaction_type),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn accept_session_key(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::AcceptSessionKey
+) -> Result<(), DatabaseError> {
+    let delegator = AddressSqlite::from(payload.delegator);
+    let session = SessionKeys::get_latest_by_session_key(conn, ctx.sender)?
+        .filter(|session| session.delegator == delegator)
+        .ok_or_else(|| DatabaseError::InvalidTransactionData(
+            format!("{} has no delegation from {}", ctx.sender, delegator)
+        ))?;
+
+    if session.accepted_transaction_id.is_some() {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("{} already accepted the delegation from {}", ctx.sender, delegator)
+        ));
+    }
+    if session.revoked_transaction_id.is_some() {
+        return Err(DatabaseError::SessionKeyRevoked(ctx.sender.0));
+    }
+    if session.is_expired(ctx) {
+        return Err(DatabaseError::SessionKeyExpired(ctx.sender.0));
+    }
+
+    conn.execute(
+        "UPDATE session_keys SET accepted_transaction_id = ?1 WHERE id = ?2",
+        (ctx.transaction_id, session.id),
+    )?;
+
+    Ok(())
+}
+
+fn revoke_session_key(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::RevokeSessionKey
+) -> Result<(), DatabaseError> {
+    let session_key = AddressSqlite::from(payload.sessionKey);
+    let session = SessionKeys::get_latest_by_session_key(conn, session_key)?
+        .filter(|session| session.delegator == ctx.sender && session.revoked_transaction_id.is_none())
+        .ok_or_else(|| DatabaseError::InvalidTransactionData(
+            format!("{} has no active delegation to {}", ctx.sender, session_key)
+        ))?;
+
+    conn.execute(
+        "UPDATE session_keys SET revoked_transaction_id = ?1 WHERE id = ?2",
+        (ctx.transaction_id, session.id),
+    )?;
+
+    Ok(())
+}
+
+// Pausing is an incident response lever, so a single admin can pause or
+// unpause without a multisig proposal
+fn pause(conn: &Connection, ctx: &Context, payload: payload::Pause) -> Result<(), DatabaseError> {
+    set_paused(conn, ctx, payload.token, true)
+}
+
+fn unpause(conn: &Connection, ctx: &Context, payload: payload::Unpause) -> Result<(), DatabaseError> {
+    set_paused(conn, ctx, payload.token, false)
+}
+
+fn set_paused(conn: &Connection, ctx: &Context, token: Address, paused: bool) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    if contract.paused == paused {
+        return Err(DatabaseError::InvalidTransactionData(format!(
+            "{} is already {}", contract.address, if paused { "paused" } else { "unpaused" }
+        )));
+    }
+
+    conn.execute(
+        "UPDATE contracts SET paused = ?1 WHERE id = ?2",
+        (paused, contract.id),
+    )?;
+
+    Ok(())
+}
+
+fn transfer_ownership(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::TransferOwnership
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    // On multisig contracts the nomination runs as a proposal, which the
+    // owner must have made
+    let account = acting_account(conn, ctx)?;
+    if account != contract.owner {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} is not the owner of {}", account, contract.address)
+        ));
+    }
+
+    let pending_owner = (payload.newOwner != Address::ZERO).then_some(AddressSqlite::from(payload.newOwner));
+    conn.execute(
+        "UPDATE contracts SET pending_owner = ?1 WHERE id = ?2",
+        (pending_owner, contract.id),
+    )?;
+
+    Ok(())
+}
+
+fn accept_ownership(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::AcceptOwnership
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    if contract.pending_owner != Some(ctx.sender) {
+        return Err(DatabaseError::Unauthorized(
+            format!("{} is not the pending owner of {}", ctx.sender, contract.address)
+        ));
+    }
+
+    conn.execute(
+        "UPDATE contracts SET owner = ?1, pending_owner = NULL WHERE id = ?2",
+        (ctx.sender, contract.id),
+    )?;
+    conn.execute(
+        "INSERT INTO ownership_history (contract_id, previous_owner, new_owner, transaction_id)
+        VALUES (?1, ?2, ?3, ?4)",
+        (contract.id, contract.owner, ctx.sender, ctx.transaction_id),
+    )?;
+
+    // The new owner takes over the roles the creator starts with. Roles the
+    // previous owner was granted on top of those stay with them
+    for role in [Role::Admin, Role::Minter, Role::UriManager] {
+        conn.execute(
+            "INSERT OR IGNORE INTO contract_signers (contract_id, signer, role, transaction_id)
+            VALUES (?1, ?2, ?3, ?4)",
+            (contract.id, ctx.sender, role, ctx.transaction_id),
+        )?;
+        if contract.owner != ctx.sender {
+            conn.execute(
+                "DELETE FROM contract_signers WHERE contract_id = ?1 AND signer = ?2 AND role = ?3",
+                (contract.id, contract.owner, role),
+            )?;
+        }
+    }
+
+    require_threshold_reachable(conn, &contract)
+}
+
+fn require_valid_royalty(bps: u16) -> Result<(), DatabaseError> {
+    if u64::from(bps) > ROYALTY_DENOMINATOR {
+        return Err(DatabaseError::InvalidTransactionData(
+            format!("royalty of {} exceeds {} basis points", bps, ROYALTY_DENOMINATOR)
+        ));
+    }
+    Ok(())
+}
+
+fn set_default_royalty(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetDefaultRoyalty
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    require_valid_royalty(payload.basisPoints)?;
+
+    let royalty = (payload.receiver != Address::ZERO)
+        .then_some((AddressSqlite::from(payload.receiver), payload.basisPoints));
+    conn.execute(
+        "UPDATE contracts SET royalty_receiver = ?1, royalty_bps = ?2 WHERE id = ?3",
+        (royalty.map(|r| r.0), royalty.map(|r| r.1), contract.id),
+    )?;
+
+    Ok(())
+}
+
+fn set_token_royalty(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetTokenRoyalty
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    require_valid_royalty(payload.basisPoints)?;
+
+    if payload.receiver == Address::ZERO {
+        conn.execute(
+            "DELETE FROM token_royalties WHERE contract_id = ?1 AND token_id = ?2",
+            (contract.id, U256Sqlite::from(payload.id)),
+        )?;
+    } else {
+        conn.execute(
+            "INSERT INTO token_royalties (contract_id, token_id, receiver, bps) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(contract_id, token_id) DO UPDATE SET receiver = excluded.receiver, bps = excluded.bps",
+            (contract.id, U256Sqlite::from(payload.id), AddressSqlite::from(payload.receiver), payload.basisPoints),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn record_contract_metadata(conn: &Connection, ctx: &Context, contract_id: i32) -> Result<(), DatabaseError> {
+    conn.execute(
+        "INSERT INTO contract_metadata_history
+            (contract_id, name, symbol, description, contract_uri, transaction_id)
+        SELECT id, name, symbol, description, contract_uri, ?1 FROM contracts WHERE id = ?2",
+        (ctx.transaction_id, contract_id),
+    )?;
+    Ok(())
+}
+
+fn set_contract_uri(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetContractURI
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::UriManager)?;
+
+    conn.execute(
+        "UPDATE contracts SET contract_uri = ?1 WHERE id = ?2",
+        (&payload.uri, contract.id),
+    )?;
+
+    record_contract_metadata(conn, ctx, contract.id)
+}
+
+fn set_contract_metadata(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetContractMetadata
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    conn.execute(
+        "UPDATE contracts SET name = ?1, symbol = ?2, description = ?3 WHERE id = ?4",
+        (&payload.name, &payload.symbol, &payload.description, contract.id),
+    )?;
+
+    record_contract_metadata(conn, ctx, contract.id)
+}
+
+// Freezing is permanent. Freezing a single token pins the URI it resolves to
+// at that moment, so later changes to the default URI cannot affect it
+fn freeze_metadata(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::FreezeMetadata
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::UriManager)?;
+    if contract.metadata_frozen {
+        return Err(DatabaseError::MetadataFrozen(format!("token URIs of {}", contract.address)));
+    }
+
+    if payload.allTokens {
+        conn.execute(
+            "UPDATE contracts SET metadata_frozen = 1 WHERE id = ?1",
+            [contract.id],
+        )?;
+        return emit_event(conn, ctx, &contract, &events::BatchMetadataFrozen {
+            _fromTokenId: U256::ZERO,
+            _toTokenId: U256::MAX,
+        });
+    }
+
+    if contract.is_token_metadata_frozen(conn, payload.id)? {
+        return Err(DatabaseError::MetadataFrozen(format!("token {} of {}", payload.id, contract.address)));
+    }
+    let uri = contract.token_uri(conn, payload.id)?.ok_or_else(|| DatabaseError::InvalidTransactionData(
+        format!("token {} of {} has no URI to freeze", payload.id, contract.address)
+    ))?;
+
+    conn.execute(
+        "INSERT INTO token_uris (contract_id, token_id, uri, transaction_id) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(contract_id, token_id) DO NOTHING",
+        (contract.id, U256Sqlite::from(payload.id), &uri, ctx.transaction_id),
+    )?;
+    conn.execute(
+        "INSERT INTO frozen_tokens (contract_id, token_id, transaction_id) VALUES (?1, ?2, ?3)",
+        (contract.id, U256Sqlite::from(payload.id), ctx.transaction_id),
+    )?;
+
+    emit_event(conn, ctx, &contract, &events::MetadataFrozen { _tokenId: payload.id })
+}
+
+fn set_relayer(conn: &Connection, ctx: &Context, payload: payload::SetRelayer) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    let relayer = AddressSqlite::from(payload.relayer);
+
+    if payload.allowed {
+        conn.execute(
+            "INSERT OR IGNORE INTO contract_relayers (contract_id, relayer, transaction_id) VALUES (?1, ?2, ?3)",
+            (contract.id, relayer, ctx.transaction_id),
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM contract_relayers WHERE contract_id = ?1 AND relayer = ?2",
+            (contract.id, relayer),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Blocking takes effect immediately but leaves existing balances and
+// approvals in place. Blocked addresses cannot use them until unblocked
+fn set_blocked(conn: &Connection, ctx: &Context, payload: payload::SetBlocked) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    let account = AddressSqlite::from(payload.account);
+
+    if payload.blocked {
+        conn.execute(
+            "INSERT OR IGNORE INTO blocked_addresses (contract_id, account, transaction_id) VALUES (?1, ?2, ?3)",
+            (contract.id, account, ctx.transaction_id),
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM blocked_addresses WHERE contract_id = ?1 AND account = ?2",
+            (contract.id, account),
+        )?;
+    }
+
+    emit_event(conn, ctx, &contract, &events::BlockedSet { account: payload.account, blocked: payload.blocked })
+}
+
+fn set_allowed_recipient(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetAllowedRecipient
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    let account = AddressSqlite::from(payload.account);
+
+    if payload.allowed {
+        conn.execute(
+            "INSERT OR IGNORE INTO allowed_recipients (contract_id, account, transaction_id) VALUES (?1, ?2, ?3)",
+            (contract.id, account, ctx.transaction_id),
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM allowed_recipients WHERE contract_id = ?1 AND account = ?2",
+            (contract.id, account),
+        )?;
+    }
+
+    emit_event(conn, ctx, &contract, &events::AllowedRecipientSet {
+        account: payload.account,
+        allowed: payload.allowed,
+    })
+}
+
+// The allowlist is kept while enforcement is off, so it can be prepared
+// before turning it on
+fn set_recipient_allowlist(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::SetRecipientAllowlist
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+
+    conn.execute(
+        "UPDATE contracts SET recipient_allowlist = ?1 WHERE id = ?2",
+        (payload.enabled, contract.id),
+    )?;
+
+    emit_event(conn, ctx, &contract, &events::RecipientAllowlistSet { enabled: payload.enabled })
+}
+
+fn set_mint_phase(conn: &Connection, ctx: &Context, payload: payload::SetMintPhase) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    let phase: MintPhase = payload.phase.parse()
+        .map_err(|_| DatabaseError::InvalidTransactionData(format!("unknown mint phase {}", payload.phase)))?;
+    if payload.endsAt != 0 && payload.endsAt <= payload.startsAt {
+        return Err(DatabaseError::InvalidTransactionData("mint phase must end after it starts".to_string()));
+    }
+    let merkle_root = (phase == MintPhase::Allowlist).then_some(payload.merkleRoot.as_slice());
+    let cap = |amount: U256| (!amount.is_zero()).then_some(U256Sqlite::from(amount));
+
+    // Changing a phase keeps what was already minted during it
+    conn.execute(
+        "INSERT INTO mint_phases
+            (contract_id, phase, merkle_root, starts_at, ends_at, transaction_id,
+                max_supply, max_per_address, unique_ids)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ON CONFLICT(contract_id, phase) DO UPDATE SET
+            merkle_root = excluded.merkle_root,
+            starts_at = excluded.starts_at,
+            ends_at = excluded.ends_at,
+            transaction_id = excluded.transaction_id,
+            max_supply = excluded.max_supply,
+            max_per_address = excluded.max_per_address,
+            unique_ids = excluded.unique_ids",
+        (
+            contract.id,
+            phase,
+            merkle_root,
+            saturating_time(payload.startsAt),
+            saturating_time(payload.endsAt),
+            ctx.transaction_id,
+            cap(payload.maxSupply),
+            cap(payload.maxPerAddress),
+            payload.uniqueIds,
+        ),
+    )?;
+
+    Ok(())
+}
+
+// Phases open and close on block timestamps, so a client cannot mint early
+// or late by choosing its own timestamp
+fn require_open_phase(
+    conn: &Connection,
+    ctx: &Context,
+    contract: &Contracts,
+    phase: MintPhase
+) -> Result<MintPhases, DatabaseError> {
+    match MintPhases::get(conn, contract.id, phase)? {
+        Some(window) if window.is_open(ctx.block_time()) => Ok(window),
+        _ => Err(DatabaseError::MintPhaseClosed(format!("{} phase of {}", phase, contract.address))),
+    }
+}
+
+// Counts a mint against the limits of the phase it happens in, on top of the
+// contract-wide caps checked by record_mint
+fn record_phase_mint(
+    conn: &Connection,
+    window: &MintPhases,
+    recipient: AddressSqlite,
+    amount: U256
+) -> Result<(), DatabaseError> {
+    let minted = window.minted.0.saturating_add(amount);
+    if let Some(max_supply) = window.max_supply {
+        if minted > max_supply.0 {
+            return Err(DatabaseError::MintLimitExceeded(format!(
+                "{} phase has {} of its supply of {} left",
+                window.phase, max_supply.0.saturating_sub(window.minted.0), max_supply
+            )));
+        }
+    }
+
+    let minted_to = window.minted_to(conn, recipient)?.saturating_add(amount);
+    if let Some(max_per_address) = window.max_per_address {
+        if minted_to > max_per_address.0 {
+            return Err(DatabaseError::MintLimitExceeded(format!(
+                "{} can mint at most {} during the {} phase", recipient, max_per_address, window.phase
+            )));
+        }
+    }
+
+    conn.execute(
+        "UPDATE mint_phases SET minted = ?1 WHERE contract_id = ?2 AND phase = ?3",
+        (U256Sqlite::from(minted), window.contract_id, window.phase),
+    )?;
+    conn.execute(
+        "INSERT INTO phase_mint_counts (contract_id, phase, recipient, amount) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(contract_id, phase, recipient) DO UPDATE SET amount = excluded.amount",
+        (window.contract_id, window.phase, recipient, U256Sqlite::from(minted_to)),
+    )?;
+
+    Ok(())
+}
+
+// Verifies a proof of the kind checked by OpenZeppelin's MerkleProof, where
+// each pair of nodes is hashed in sorted order
+fn verify_merkle_proof(root: &[u8], leaf: B256, proof: &[B256]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak256([node.as_slice(), sibling.as_slice()].concat())
+        } else {
+            keccak256([sibling.as_slice(), node.as_slice()].concat())
+        }
+    });
+    computed.as_slice() == root
+}
+
+fn claim_allowlist(conn: &Connection, ctx: &Context, payload: payload::ClaimAllowlist) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    let window = require_open_phase(conn, ctx, &contract, MintPhase::Allowlist)?;
+
+    let leaf = keccak256(keccak256((ctx.sender.0, payload.id, payload.quantity).abi_encode()));
+    if !verify_merkle_proof(window.merkle_root.as_deref().unwrap_or_default(), leaf, &payload.proof) {
+        return Err(DatabaseError::InvalidMerkleProof(
+            format!("{} is not allowlisted for {} of token {}", ctx.sender, payload.quantity, payload.id)
+        ));
+    }
+
+    let claimed: Option<U256Sqlite> = conn.query_row(
+        "SELECT claimed FROM allowlist_claims WHERE contract_id = ? AND leaf = ?",
+        (contract.id, leaf.as_slice()),
+        |row| row.get(0)
+    ).optional()?;
+    // In phases with unique ids a leaf mints a fresh id, and later parts of
+    // its claim add to it
+    if window.unique_ids && claimed.is_none() {
+        require_unminted(conn, &contract, payload.id, payload.id)?;
+    }
+    let claimed = claimed.map_or(U256::ZERO, |claimed| claimed.0).saturating_add(payload.amount);
+    if claimed > payload.quantity {
+        return Err(DatabaseError::MintLimitExceeded(
+            format!("{} can claim at most {} of token {}", ctx.sender, payload.quantity, payload.id)
+        ));
+    }
+    conn.execute(
+        "INSERT INTO allowlist_claims (contract_id, leaf, claimed) VALUES (?1, ?2, ?3)
+        ON CONFLICT(contract_id, leaf) DO UPDATE SET claimed = excluded.claimed",
+        (contract.id, leaf.as_slice(), U256Sqlite::from(claimed)),
+    )?;
+
+    record_phase_mint(conn, &window, ctx.sender, payload.amount)?;
+    mint_to(conn, &contract, ctx.sender.0, payload.id, payload.amount)
+}
+
+// Public mints are limited by the phase and the contract's supply caps. In
+// phases with unique ids they only mint ids nobody holds yet
+fn public_mint(conn: &Connection, ctx: &Context, payload: payload::PublicMint) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    let window = require_open_phase(conn, ctx, &contract, MintPhase::Public)?;
+    if window.unique_ids {
+        require_unminted(conn, &contract, payload.id, payload.id)?;
+    }
+
+    record_phase_mint(conn, &window, ctx.sender, payload.amount)?;
+    mint_to(conn, &contract, ctx.sender.0, payload.id, payload.amount)
+}
+
+// Marking is permanent, so ids that were handed out as badges can never
+// become tradeable again
+fn mark_soulbound(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::MarkSoulbound
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    if contract.is_soulbound(conn, payload.id)? {
+        return Err(DatabaseError::Soulbound(format!("token {} of {}", payload.id, contract.address)));
+    }
+
+    conn.execute(
+        "INSERT INTO soulbound_tokens (contract_id, token_id, transaction_id) VALUES (?1, ?2, ?3)",
+        (contract.id, U256Sqlite::from(payload.id), ctx.transaction_id),
+    )?;
+
+    emit_event(conn, ctx, &contract, &events::Locked { tokenId: payload.id })
+}
+
+// Clawback skips the holder's approval but nothing else, so paused
+// contracts and soulbound tokens stay untouched. Blocked holders can still
+// have tokens clawed back, but the recipient must be allowed to receive
+fn force_transfer(conn: &Connection, ctx: &Context, payload: payload::ForceTransfer) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Clawback)?;
+    if contract.clawback_disabled {
+        return Err(DatabaseError::ClawbackDisabled(contract.address.0));
+    }
+    require_not_paused(&contract)?;
+    require_transferable(conn, &contract, payload.id)?;
+    require_valid_recipient(payload.to)?;
+    require_valid_amount(payload.amount)?;
+
+    let from = AddressSqlite::from(payload.from);
+    let to = AddressSqlite::from(payload.to);
+    require_allowed_recipient(conn, &contract, to)?;
+    debit(conn, contract.id, from, payload.id, payload.amount)?;
+    credit(conn, contract.id, to, payload.id, payload.amount)?;
+    clear_token_approval(conn, contract.id, from, payload.id)?;
+    clear_token_user(conn, ctx, &contract, payload.id)?;
+
+    conn.execute(
+        "UPDATE receipts SET clawback = 1 WHERE transaction_id = ?1",
+        [ctx.transaction_id],
+    )?;
+    emit_event(conn, ctx, &contract, &events::Clawback {
+        operator: ctx.sender.0,
+        from: payload.from,
+        to: payload.to,
+        id: payload.id,
+        amount: payload.amount,
+    })
+}
+
+// Lets a contract commit to never clawing back again, e.g. once a game has
+// shut down. Like freezing metadata, this cannot be undone
+fn disable_clawback(
+    conn: &Connection,
+    ctx: &Context,
+    payload: payload::DisableClawback
+) -> Result<(), DatabaseError> {
+    let contract = contract_for(conn, payload.token)?;
+    require_role(conn, ctx, &contract, Role::Admin)?;
+    if contract.clawback_disabled {
+        return Err(DatabaseError::ClawbackDisabled(contract.address.0));
+    }
+
+    conn.execute(
+        "UPDATE contracts SET clawback_disabled = 1 WHERE id = ?1",
+        [contract.id],
+    )?;
+
+    emit_event(conn, ctx, &contract, &events::ClawbackDisabled {})
+}
+
+// ERC-2981 royaltyInfo. The per-token royalty takes precedence over the
+// contract default. Without either, the zero address and no royalty are
+// returned
+fn royalty_info(
+    conn: &Connection,
+    token: Address,
+    id: U256,
+    sale_price: U256
+) -> Result<(Address, U256), DatabaseError> {
+    let contract = contract_for(conn, token)?;
+    let token_royalty: Option<(AddressSqlite, u16)> = conn.query_row(
+        "SELECT receiver, bps FROM token_royalties WHERE contract_id = ? AND token_id = ?",
+        (contract.id, U256Sqlite::from(id)),
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).optional()?;
+    let default_royalty = contract.royalty_receiver.zip(contract.royalty_bps);
+
+    let Some((receiver, bps)) = token_royalty.or(default_royalty) else {
+        return Ok((Address::ZERO, U256::ZERO));
+    };
+
+    // Split the multiplication so that it cannot overflow for any sale price
+    let denominator = U256::from(ROYALTY_DENOMINATOR);
+    let bps = U256::from(bps);
+    let amount = sale_price / denominator * bps + sale_price % denominator * bps / denominator;
+    Ok((receiver.0, amount))
+}
+
+// Answers an eth_call made to a contract address. `data` is the ABI-encoded
+// call including its selector, and the ABI-encoded return data is returned
+fn eth_call(conn: &Connection, to: Address, data: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+    use calls::IMintVM;
+
+    // Calls to addresses that are not MintVM contracts are rejected
+    let contract = contract_for(conn, to)?;
+    let call = IMintVM::IMintVMCalls::abi_decode(data, true)
+        .map_err(|e| DatabaseError::InvalidCall(e.to_string()))?;
+
+    let output = match call {
+        IMintVM::IMintVMCalls::supportsInterface(call) => {
+            let supported = SUPPORTED_INTERFACES.contains(&call.interfaceId.0);
+            IMintVM::supportsInterfaceCall::abi_encode_returns(&(supported,))
+        }
+        IMintVM::IMintVMCalls::royaltyInfo(call) => {
+            let (receiver, amount) = royalty_info(conn, to, call.tokenId, call.salePrice)?;
+            IMintVM::royaltyInfoCall::abi_encode_returns(&(receiver, amount))
+        }
+        IMintVM::IMintVMCalls::name(_) => IMintVM::nameCall::abi_encode_returns(&(contract.name,)),
+        IMintVM::IMintVMCalls::symbol(_) => IMintVM::symbolCall::abi_encode_returns(&(contract.symbol,)),
+        IMintVM::IMintVMCalls::contractURI(_) => {
+            IMintVM::contractURICall::abi_encode_returns(&(contract.contract_uri.unwrap_or_default(),))
+        }
+        IMintVM::IMintVMCalls::locked(call) => {
+            IMintVM::lockedCall::abi_encode_returns(&(contract.is_soulbound(conn, call.tokenId)?,))
+        }
+        IMintVM::IMintVMCalls::balanceOf(call) => {
+            let balance = Balances::balance_of(conn, contract.id, AddressSqlite::from(call.account), ERC20_TOKEN_ID)?;
+            IMintVM::balanceOfCall::abi_encode_returns(&(balance,))
+        }
+        IMintVM::IMintVMCalls::allowance(call) => {
+            let owner = AddressSqlite::from(call.owner);
+            let allowance = Allowances::allowance(conn, contract.id, owner, AddressSqlite::from(call.spender))?;
+            IMintVM::allowanceCall::abi_encode_returns(&(allowance,))
+        }
+        IMintVM::IMintVMCalls::nonces(call) => {
+            let nonce = permit_nonce(conn, contract.id, AddressSqlite::from(call.owner))?;
+            IMintVM::noncesCall::abi_encode_returns(&(U256::from(nonce),))
+        }
+        IMintVM::IMintVMCalls::DOMAIN_SEPARATOR(_) => {
+            IMintVM::DOMAIN_SEPARATORCall::abi_encode_returns(&(permit_domain(conn, &contract)?.separator(),))
+        }
+        IMintVM::IMintVMCalls::userOf(call) => {
+            IMintVM::userOfCall::abi_encode_returns(&(user_of(conn, to, call.tokenId)?,))
+        }
+        // The expiry is reported as stored, whether or not it has passed
+        IMintVM::IMintVMCalls::userExpires(call) => {
+            let expires = TokenUsers::get(conn, contract.id, call.tokenId)?
+                .map_or(0, |token_user| u64::try_from(token_user.expires).unwrap_or_default());
+            IMintVM::userExpiresCall::abi_encode_returns(&(U256::from(expires),))
+        }
+        // Like ERC-721, ownerOf fails for tokens that do not exist
+        IMintVM::IMintVMCalls::ownerOf(call) => {
+            let owner = contract.owner_of(conn, call.tokenId)?.ok_or_else(|| DatabaseError::InvalidCall(
+                format!("token {} of {} does not exist", call.tokenId, contract.address)
+            ))?;
+            IMintVM::ownerOfCall::abi_encode_returns(&(owner.0,))
+        }
+    };
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_main() {
+        assert!(main().is_ok());
+    }
+
+    #[test]
+    fn test_insert_transaction() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let sender = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
+        // An empty CreateToken payload creates an uncapped contract
+        let test_data = Vec::new();
+        let test_timestamp = 1715136000;
+
+        let transaction = Transactions {
+            id: 0,
+            sender,
+            transaction_type: TransactionType::CreateToken,
+            data: test_data.clone(),
+            timestamp: test_timestamp,
+            signature: None,
+            relayer: None,
+            relayer_signature: None,
+            block_number: None,
+        };
+        insert_transaction(&mut conn, &transaction)?;
+
+        // Use getter instead of direct row access
+        let saved_transaction = Transactions::get_by_id(&conn, 1)?;
+        
+        assert_eq!(saved_transaction.id, 1); // First record should have ID 1
+        assert_eq!(saved_transaction.sender, sender);
+        assert_eq!(saved_transaction.transaction_type, TransactionType::CreateToken);
+        assert_eq!(saved_transaction.data, test_data);
+        assert_eq!(saved_transaction.timestamp, test_timestamp);
+
+        // Verify contract was created
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        assert_eq!(contract.transaction_id, 1);
+        assert_eq!(contract.signers.0, vec![sender]); // Verify sender is set as initial signer
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_contract() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let sender = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
+        
+        // First insert a transaction that will create a contract
         let transaction = Transactions {
             id: 0,
             sender,
-            transaction_type: TransactionType::CreateToken,
-            data: test_data.clone(),
-            timestamp: test_timestamp,
+            transaction_type: TransactionType::CreateToken,
+            data: Vec::new(),
+            timestamp: 1715136000,
+            signature: None,
+            relayer: None,
+            relayer_signature: None,
+            block_number: None,
+        };
+        insert_transaction(&mut conn, &transaction)?;
+
+        // Get contract by ID
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        assert_eq!(contract.id, 1);
+        assert_eq!(contract.transaction_id, 1);
+        assert_eq!(contract.signers.0, vec![sender]);
+
+        // Get contract by address and verify it matches
+        let contract_by_addr = Contracts::get_by_address(&conn, contract.address)?;
+        assert_eq!(contract_by_addr.id, contract.id);
+        assert_eq!(contract_by_addr.address, contract.address);
+        assert_eq!(contract_by_addr.signers.0, contract.signers.0);
+        assert_eq!(contract_by_addr.transaction_id, contract.transaction_id);
+
+        // Get contract by transaction ID and verify it matches
+        let contract_by_tx = Contracts::get_by_transaction_id(&conn, 1)?;
+        assert_eq!(contract_by_tx.id, contract.id);
+        assert_eq!(contract_by_tx.address, contract.address);
+        assert_eq!(contract_by_tx.signers.0, contract.signers.0);
+        assert_eq!(contract_by_tx.transaction_id, contract.transaction_id);
+
+        Ok(())
+    }
+
+    // Returns the `detail` column of EXPLAIN QUERY PLAN for the given query.
+    // Parameters are left unbound since only the plan is of interest
+    fn query_plan(conn: &Connection, sql: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {}", sql))?;
+        let mut rows = stmt.raw_query();
+        let mut details = Vec::new();
+        while let Some(row) = rows.next()? {
+            details.push(row.get(3)?);
+        }
+        Ok(details)
+    }
+
+    // Asserts the query is answered through one of the given indexes without
+    // falling back to a table scan
+    fn assert_uses_index(conn: &Connection, sql: &str, indexes: &[&str]) {
+        let plan = query_plan(conn, sql).unwrap();
+        assert!(
+            plan.iter().any(|detail| indexes.iter().any(|index| detail.contains(&format!("INDEX {} ", index)))),
+            "expected `{}` to use one of {:?}, got plan {:?}", sql, indexes, plan
+        );
+        // Scanning the rows of a subquery is fine, scanning a table is not
+        assert!(
+            !plan.iter().any(|detail| detail.starts_with("SCAN") && !detail.starts_with("SCAN (subquery")),
+            "expected `{}` not to scan, got plan {:?}", sql, plan
+        );
+    }
+
+    #[test]
+    fn test_transaction_getters_use_indexes() -> Result<(), Box<dyn std::error::Error>> {
+        let conn = initialize_db()?;
+
+        assert_uses_index(&conn, Transactions::SELECT_BY_SENDER, &["idx_transactions_sender"]);
+        // Either composite index can serve an equality match on the type alone
+        assert_uses_index(
+            &conn,
+            Transactions::SELECT_BY_TYPE,
+            &["idx_transactions_type_sender", "idx_transactions_type_timestamp"]
+        );
+        assert_uses_index(&conn, Transactions::SELECT_BY_TYPE_AND_SENDER, &["idx_transactions_type_sender"]);
+        assert_uses_index(&conn, Transactions::SELECT_BY_TYPE_AFTER_TIMESTAMP, &["idx_transactions_type_timestamp"]);
+        assert_uses_index(&conn, Transactions::SELECT_BY_RELAYER, &["idx_transactions_relayer"]);
+
+        // Lookups by primary key go through the rowid rather than an index
+        let plan = query_plan(&conn, Transactions::SELECT_BY_ID)?;
+        assert!(plan.iter().any(|detail| detail.contains("INTEGER PRIMARY KEY")), "{:?}", plan);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contract_getters_use_indexes() -> Result<(), Box<dyn std::error::Error>> {
+        let conn = initialize_db()?;
+
+        // The UNIQUE constraints on contracts create the autoindexes
+        assert_uses_index(&conn, Contracts::SELECT_BY_ADDRESS, &["sqlite_autoindex_contracts_1"]);
+        assert_uses_index(&conn, Contracts::SELECT_BY_SIGNER, &["idx_contract_signers_signer"]);
+        assert_uses_index(&conn, Contracts::SELECT_BY_TRANSACTION_ID, &["sqlite_autoindex_contracts_2"]);
+        assert_uses_index(&conn, Contracts::SELECT_BY_BLOCKED, &["idx_blocked_addresses_account"]);
+        assert_uses_index(&conn, Contracts::SELECT_IS_BLOCKED, &["sqlite_autoindex_blocked_addresses_1"]);
+        assert_uses_index(
+            &conn,
+            Contracts::SELECT_IS_ALLOWED_RECIPIENT,
+            &["sqlite_autoindex_allowed_recipients_1"],
+        );
+
+        let plan = query_plan(&conn, Contracts::SELECT_BY_ID)?;
+        assert!(plan.iter().any(|detail| detail.contains("INTEGER PRIMARY KEY")), "{:?}", plan);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        
+        // Create test data
+        let sender1 = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
+        let sender2 = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000002").unwrap());
+
+        // The first CreateToken is transaction 1, so its address is known
+        // before it is inserted
+        let token1: AddressSqlite = conn.query_row("SELECT derive_contract_address(1)", [], |row| row.get(0))?;
+        let token2_data = payload::CreateToken {
+            maxSupply: U256::from(2),
+            ..Default::default()
+        }.abi_encode();
+        
+        let test_transactions = vec![
+            Transactions {
+                id: 0,
+                sender: sender1,
+                transaction_type: TransactionType::CreateToken,
+                data: payload::CreateToken { maxSupply: U256::from(1), ..Default::default() }.abi_encode(),
+                timestamp: 1000,
+                signature: None,
+                relayer: None,
+                relayer_signature: None,
+                block_number: None,
+            },
+            Transactions {
+                id: 0,
+                sender: sender1,
+                transaction_type: TransactionType::Mint,
+                data: payload::Mint {
+                    token: token1.0,
+                    to: sender2.0,
+                    id: U256::from(1),
+                    amount: U256::from(1),
+                }.abi_encode(),
+                timestamp: 1001,
+                signature: None,
+                relayer: None,
+                relayer_signature: None,
+                block_number: None,
+            },
+            Transactions {
+                id: 0,
+                sender: sender2,
+                transaction_type: TransactionType::CreateToken,
+                data: token2_data.clone(),
+                timestamp: 1002,
+                signature: None,
+                relayer: None,
+                relayer_signature: None,
+                block_number: None,
+            },
+            Transactions {
+                id: 0,
+                sender: sender2,
+                transaction_type: TransactionType::Transfer,
+                data: payload::Transfer {
+                    token: token1.0,
+                    from: sender2.0,
+                    to: sender1.0,
+                    id: U256::from(1),
+                    amount: U256::from(1),
+                }.abi_encode(),
+                timestamp: 1003,
+                signature: None,
+                relayer: None,
+                relayer_signature: None,
+                block_number: None,
+            },
+        ];
+
+        // Insert all transactions
+        for tx in &test_transactions {
+            insert_transaction(&mut conn, tx)?;
+        }
+
+        // Test different query methods
+        
+        // 1. Get all CreateToken transactions
+        let create_txs = Transactions::get_by_type(&conn, TransactionType::CreateToken)?;
+        assert_eq!(create_txs.len(), 2);
+        assert!(create_txs.iter().all(|tx| tx.transaction_type == TransactionType::CreateToken));
+
+        // 2. Get all transactions from sender1
+        let sender1_txs = Transactions::get_by_sender(&conn, sender1)?;
+        assert_eq!(sender1_txs.len(), 2);
+        assert!(sender1_txs.iter().all(|tx| tx.sender == sender1));
+
+        // 3. Get CreateToken transactions from sender2
+        let sender2_create_txs = Transactions::get_by_type_and_sender(
+            &conn,
+            TransactionType::CreateToken,
+            sender2
+        )?;
+        assert_eq!(sender2_create_txs.len(), 1);
+        assert_eq!(sender2_create_txs[0].data, token2_data);
+
+        // 4. Get transactions after timestamp 1001
+        let recent_txs = Transactions::get_by_type_after_timestamp(
+            &conn,
+            TransactionType::CreateToken,
+            1001
+        )?;
+        assert_eq!(recent_txs.len(), 1);
+        assert_eq!(recent_txs[0].sender, sender2);
+
+        // 5. Verify we can get each transaction by ID
+        for i in 1..=4 {
+            let tx = Transactions::get_by_id(&conn, i)?;
+            println!("Transaction {}: {:?}", i, tx);
+        }
+
+        Ok(())
+    }
+
+    fn address(n: u8) -> AddressSqlite {
+        AddressSqlite::from(Address::with_last_byte(n))
+    }
+
+    // Builds a transaction with an ABI-encoded payload
+    fn transaction(sender: AddressSqlite, transaction_type: TransactionType, data: impl SolValue) -> Transactions {
+        Transactions {
+            id: 0,
+            sender,
+            transaction_type,
+            data: data.abi_encode(),
+            timestamp: 1715136000,
+            signature: None,
+            relayer: None,
+            relayer_signature: None,
+            block_number: None,
+        }
+    }
+
+    // Deterministic test key whose address can be used as a sender
+    fn signing_key(n: u8) -> k256::ecdsa::SigningKey {
+        k256::ecdsa::SigningKey::from_slice(&[n; 32]).unwrap()
+    }
+
+    fn key_address(key: &k256::ecdsa::SigningKey) -> AddressSqlite {
+        AddressSqlite::from(Address::from_private_key(key))
+    }
+
+    // Signs EIP-712 typed data for `token` the way a wallet would
+    fn sign_typed<T: SolStruct>(key: &k256::ecdsa::SigningKey, token: Address, data: &T) -> Vec<u8> {
+        sign_hash(key, &data.eip712_signing_hash(&eip712_domain(DEFAULT_CHAIN_ID, Some(token))))
+    }
+
+    fn sign_hash(key: &k256::ecdsa::SigningKey, hash: &B256) -> Vec<u8> {
+        let signature: PrimitiveSignature = key.sign_prehash_recoverable(hash.as_slice()).unwrap().into();
+        signature.as_bytes().to_vec()
+    }
+
+    // Creates a contract owned by `sender` and returns its address
+    fn new_token(conn: &mut Connection, sender: AddressSqlite) -> Result<Address, Box<dyn std::error::Error>> {
+        let receipt = insert_transaction(conn, &Transactions {
+            id: 0,
+            sender,
+            transaction_type: TransactionType::CreateToken,
+            data: Vec::new(),
+            timestamp: 1715136000,
+            signature: None,
+            relayer: None,
+            relayer_signature: None,
+            block_number: None,
+        })?;
+        let contract = Contracts::get_by_transaction_id(conn, receipt.transaction_id)?;
+        Ok(contract.address.0)
+    }
+
+    #[test]
+    fn test_add_and_remove_token_signer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let creator = address(1);
+        let token = new_token(&mut conn, creator)?;
+
+        insert_transaction(&mut conn, &transaction(
+            creator,
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: address(2).0 },
+        ))?;
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.signers.0, vec![creator, address(2)]);
+
+        // Each role keeps the transaction that granted it. The creator holds
+        // every role and the added signer can mint
+        let signers = ContractSigners::get_by_contract_id(&conn, contract.id)?;
+        let roles: Vec<(AddressSqlite, Role, i32)> = signers.iter()
+            .map(|s| (s.signer, s.role, s.transaction_id))
+            .collect();
+        assert_eq!(roles, vec![
+            (creator, Role::Admin, 1),
+            (creator, Role::Minter, 1),
+            (creator, Role::UriManager, 1),
+            (address(2), Role::Minter, 2),
+        ]);
+
+        // Signers without the Admin role cannot manage signers
+        let result = insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::RemoveTokenSigner,
+            payload::RemoveTokenSigner { token, signer: creator.0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Once made an admin, the added signer can remove the creator
+        insert_transaction(&mut conn, &transaction(
+            creator,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: address(2).0, role: "Admin".to_string() },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::RemoveTokenSigner,
+            payload::RemoveTokenSigner { token, signer: creator.0 },
+        ))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.signers.0, vec![address(2)]);
+
+        // The last admin cannot be removed
+        let result = insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::RemoveTokenSigner,
+            payload::RemoveTokenSigner { token, signer: address(2).0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_signer_change_is_not_recorded() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let token = new_token(&mut conn, address(1))?;
+
+        // Only signers may add signers
+        let result = insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: address(2).0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Unknown contracts are rejected
+        let result = insert_transaction(&mut conn, &transaction(
+            address(1),
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token: Address::ZERO, signer: address(2).0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::UnknownContract(_))));
+
+        // Neither rejected transaction was stored
+        assert_eq!(Transactions::get_by_type(&conn, TransactionType::AddTokenSigner)?.len(), 0);
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.signers.0, vec![address(1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_contracts_by_signer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let first = new_token(&mut conn, address(1))?;
+        let second = new_token(&mut conn, address(2))?;
+        new_token(&mut conn, address(3))?;
+
+        insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token: second, signer: address(1).0 },
+        ))?;
+
+        let contracts = Contracts::get_by_signer(&conn, address(1))?;
+        let addresses: Vec<Address> = contracts.iter().map(|c| c.address.0).collect();
+        assert_eq!(addresses, vec![first, second]);
+        assert_eq!(contracts[1].signers.0, vec![address(2), address(1)]);
+
+        assert!(Contracts::get_by_signer(&conn, address(4))?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_role_checks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let minter = address(2);
+        let token = new_token(&mut conn, admin)?;
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: minter.0, role: "Minter".to_string() },
+        ))?;
+
+        // Granting a role makes the account a signer
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.signers.0, vec![admin, minter]);
+
+        insert_transaction(&mut conn, &transaction(
+            minter,
+            TransactionType::Mint,
+            payload::Mint { token, to: address(3).0, id: U256::from(7), amount: U256::from(1) },
+        ))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, address(3), U256::from(7))?, U256::from(1));
+
+        // The minter can neither change metadata nor manage roles
+        let result = insert_transaction(&mut conn, &transaction(
+            minter,
+            TransactionType::SetDefaultTokenURI,
+            payload::SetDefaultTokenURI { token, uri: "ipfs://evil/".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            minter,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: minter.0, role: "Admin".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Unknown role names are rejected
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: minter.0, role: "Owner".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        // Revoking the only role removes the account from the signers
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::RevokeRole,
+            payload::RevokeRole { token, account: minter.0, role: "Minter".to_string() },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            minter,
+            TransactionType::Mint,
+            payload::Mint { token, to: address(3).0, id: U256::from(8), amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.signers.0, vec![admin]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_role_checks_apply_to_proposals() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (admin, minter) = (address(1), address(2));
+        let token = new_token(&mut conn, admin)?;
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: minter.0 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        // Each proposal is approved by the other signer and then executed
+        let mut proposals = 0;
+        let mut run_proposal = |
+            conn: &mut Connection,
+            proposer: AddressSqlite,
+            transaction_type: TransactionType,
+            data: Vec<u8>
+        | {
+            proposals += 1;
+            let approver = if proposer == admin { minter } else { admin };
+            insert_transaction(conn, &transaction(
+                proposer,
+                TransactionType::ProposeOperation,
+                payload::ProposeOperation {
+                    token,
+                    transactionType: transaction_type.to_string(),
+                    data: data.into(),
+                    expiresAt: 1715136000 + 3600,
+                },
+            ))?;
+            insert_transaction(conn, &transaction(
+                approver,
+                TransactionType::ApproveProposal,
+                payload::ApproveProposal { token, proposalId: proposals },
+            ))?;
+            insert_transaction(conn, &transaction(
+                approver,
+                TransactionType::ExecuteProposal,
+                payload::ExecuteProposal { token, proposalId: proposals },
+            ))
+        };
+
+        // The minter cannot change URIs through a proposal either
+        let set_uri = payload::SetDefaultTokenURI { token, uri: "ipfs://new/".to_string() };
+        let result = run_proposal(&mut conn, minter, TransactionType::SetDefaultTokenURI, set_uri.abi_encode());
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Until an admin's proposal grants the role
+        let grant = payload::GrantRole { token, account: minter.0, role: "UriManager".to_string() };
+        run_proposal(&mut conn, admin, TransactionType::GrantRole, grant.abi_encode())?;
+        run_proposal(&mut conn, minter, TransactionType::SetDefaultTokenURI, set_uri.abi_encode())?;
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.default_token_uri.as_deref(), Some("ipfs://new/"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_uris() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetDefaultTokenURI,
+            payload::SetDefaultTokenURI { token, uri: "ipfs://default/{id}".to_string() },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetTokenURIPerId,
+            payload::SetTokenURIPerId { token, id: U256::from(2), uri: "ipfs://two".to_string() },
+        ))?;
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.token_uri(&conn, U256::from(1))?.as_deref(), Some("ipfs://default/{id}"));
+        assert_eq!(contract.token_uri(&conn, U256::from(2))?.as_deref(), Some("ipfs://two"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transfers_and_approvals() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let spender = address(3);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let id = U256::from(1);
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: holder.0, id, amount: U256::from(10) },
+        ))?;
+
+        // Spending someone else's tokens requires an approval
+        let move_tokens = |from: AddressSqlite, to: AddressSqlite, amount: u64| payload::Transfer {
+            token, from: from.0, to: to.0, id, amount: U256::from(amount),
+        };
+        let result = insert_transaction(&mut conn, &transaction(
+            spender, TransactionType::Transfer, move_tokens(holder, spender, 1),
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Approve,
+            payload::Approve { token, owner: holder.0, spender: spender.0, id },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            spender, TransactionType::Transfer, move_tokens(holder, spender, 4),
+        ))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, id)?, U256::from(6));
+        assert_eq!(Balances::balance_of(&conn, contract.id, spender, id)?, U256::from(4));
+
+        // The approval is cleared by the transfer
+        let result = insert_transaction(&mut conn, &transaction(
+            spender, TransactionType::Transfer, move_tokens(holder, spender, 1),
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Operators can move and burn any of the holder's tokens
+        insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::SetApprovalForAll,
+            payload::SetApprovalForAll { token, operator: spender.0, approved: true },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            spender,
+            TransactionType::Burn,
+            payload::Burn { token, from: holder.0, id, amount: U256::from(6) },
+        ))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, id)?, U256::ZERO);
+
+        // Nothing left to move
+        let result = insert_transaction(&mut conn, &transaction(
+            holder, TransactionType::Transfer, move_tokens(holder, spender, 1),
+        ));
+        assert!(matches!(result, Err(DatabaseError::InsufficientBalance(_))));
+
+        let balances = Balances::get_by_owner(&conn, spender)?;
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].amount, U256Sqlite::from(U256::from(4)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig_proposals() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (first, second, third) = (address(1), address(2), address(3));
+        let token = new_token(&mut conn, first)?;
+        insert_block(&conn, 1, 1715136000)?;
+
+        for signer in [second, third] {
+            insert_transaction(&mut conn, &transaction(
+                first,
+                TransactionType::AddTokenSigner,
+                payload::AddTokenSigner { token, signer: signer.0 },
+            ))?;
+        }
+        insert_transaction(&mut conn, &transaction(
+            first,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        // The admin can no longer change URIs on their own
+        let set_uri = payload::SetDefaultTokenURI { token, uri: "ipfs://new/".to_string() };
+        let result = insert_transaction(&mut conn, &transaction(
+            first, TransactionType::SetDefaultTokenURI, set_uri.clone(),
+        ));
+        assert!(matches!(result, Err(DatabaseError::MultisigRequired(_))));
+
+        // Minting is not sensitive and is unaffected
+        insert_transaction(&mut conn, &transaction(
+            second,
+            TransactionType::Mint,
+            payload::Mint { token, to: second.0, id: U256::from(1), amount: U256::from(1) },
+        ))?;
+
+        insert_transaction(&mut conn, &transaction(
+            first,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::SetDefaultTokenURI.to_string(),
+                data: set_uri.abi_encode().into(),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let proposal = &Proposals::get_by_contract_id(&conn, contract.id)?[0];
+
+        // The proposer's approval alone is below the threshold
+        let execute = payload::ExecuteProposal { token, proposalId: proposal.id as u64 };
+        let result = insert_transaction(&mut conn, &transaction(
+            second, TransactionType::ExecuteProposal, execute.clone(),
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            third,
+            TransactionType::ApproveProposal,
+            payload::ApproveProposal { token, proposalId: proposal.id as u64 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            second, TransactionType::ExecuteProposal, execute.clone(),
+        ))?;
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.default_token_uri.as_deref(), Some("ipfs://new/"));
+        assert!(Proposals::get_by_id(&conn, proposal.id)?.executed_transaction_id.is_some());
+
+        // Proposals only execute once
+        let result = insert_transaction(&mut conn, &transaction(
+            second, TransactionType::ExecuteProposal, execute,
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig_proposals_check_proposer_roles() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (admin, minter, cosigner, holder) = (address(1), address(2), address(3), address(4));
+        let token = new_token(&mut conn, admin)?;
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: holder.0, id: U256::from(1), amount: U256::from(1) },
+        ))?;
+
+        // Without a threshold above 1 the proposer could approve alone
+        let grant = payload::GrantRole { token, account: minter.0, role: Role::Admin.to_string() };
+        let propose = |sender: AddressSqlite, transaction_type: TransactionType, data: Vec<u8>| transaction(
+            sender,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: transaction_type.to_string(),
+                data: data.into(),
+                expiresAt: 1715136000 + 3600,
+            },
+        );
+        let result = insert_transaction(
+            &mut conn, &propose(admin, TransactionType::GrantRole, grant.abi_encode()),
+        );
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        for signer in [minter, cosigner] {
+            insert_transaction(&mut conn, &transaction(
+                admin,
+                TransactionType::AddTokenSigner,
+                payload::AddTokenSigner { token, signer: signer.0 },
+            ))?;
+        }
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        // A quorum approving a minter's proposal does not lend the minter the
+        // roles or ownership the operation requires
+        let force = payload::ForceTransfer {
+            token, from: holder.0, to: minter.0, id: U256::from(1), amount: U256::from(1),
+        };
+        let nominate = payload::TransferOwnership { token, newOwner: minter.0 };
+        let proposals = [
+            (TransactionType::GrantRole, grant.abi_encode()),
+            (TransactionType::ForceTransfer, force.abi_encode()),
+            (TransactionType::TransferOwnership, nominate.abi_encode()),
+        ];
+        for (proposal_id, (transaction_type, data)) in (1u64..).zip(proposals) {
+            insert_transaction(&mut conn, &propose(minter, transaction_type, data))?;
+            insert_transaction(&mut conn, &transaction(
+                cosigner,
+                TransactionType::ApproveProposal,
+                payload::ApproveProposal { token, proposalId: proposal_id },
+            ))?;
+            let result = insert_transaction(&mut conn, &transaction(
+                admin,
+                TransactionType::ExecuteProposal,
+                payload::ExecuteProposal { token, proposalId: proposal_id },
+            ));
+            assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        }
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert!(!ContractSigners::has_role(&conn, contract.id, minter, Role::Admin)?);
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, U256::from(1))?, U256::from(1));
+        assert_eq!(contract.pending_owner, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multisig_proposal_expiry() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (first, second) = (address(1), address(2));
+        let token = new_token(&mut conn, first)?;
+        insert_block(&conn, 1, 1715136000)?;
+
+        insert_transaction(&mut conn, &transaction(
+            first,
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: second.0 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            first,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        // Only sensitive operations can be proposed
+        let mint = payload::Mint { token, to: first.0, id: U256::from(1), amount: U256::from(1) };
+        let result = insert_transaction(&mut conn, &transaction(
+            first,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::Mint.to_string(),
+                data: mint.abi_encode().into(),
+                expiresAt: 1715136000 + 60,
+            },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionType(_))));
+
+        // Sensitive operations can be proposed with an expiry
+        let remove = payload::RemoveTokenSigner { token, signer: second.0 };
+        let propose = |expires_at: u64| transaction(
+            first,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::RemoveTokenSigner.to_string(),
+                data: remove.abi_encode().into(),
+                expiresAt: expires_at,
+            },
+        );
+        let result = insert_transaction(&mut conn, &propose(1715136000));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        insert_transaction(&mut conn, &propose(1715136000 + 60))?;
+
+        // Approvals in a block at or after the expiry are rejected, whatever
+        // timestamp the client sends
+        insert_block(&conn, 2, 1715136000 + 60)?;
+        let approve = transaction(
+            second,
+            TransactionType::ApproveProposal,
+            payload::ApproveProposal { token, proposalId: 1 },
+        );
+        let result = insert_transaction(&mut conn, &approve);
+        assert!(matches!(result, Err(DatabaseError::ProposalExpired(1))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_keys() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (signer, session_key, player) = (address(1), address(9), address(3));
+        let token = new_token(&mut conn, signer)?;
+        let other_token = new_token(&mut conn, signer)?;
+        insert_block(&conn, 1, 1715136000)?;
+
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::DelegateSessionKey,
+            payload::DelegateSessionKey {
+                sessionKey: session_key.0,
+                tokens: vec![token],
+                transactionTypes: vec![TransactionType::Mint.to_string()],
+                maxMints: U256::from(3),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+
+        let mint = |token: Address, amount: u64| payload::Mint {
+            token, to: player.0, id: U256::from(1), amount: U256::from(amount),
+        };
+
+        // Until the key accepts, it sends as itself and has no roles
+        let result = insert_transaction(&mut conn, &transaction(session_key, TransactionType::Mint, mint(token, 2)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            session_key,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: player.0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        insert_transaction(&mut conn, &transaction(
+            session_key,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: signer.0 },
+        ))?;
+
+        insert_transaction(&mut conn, &transaction(session_key, TransactionType::Mint, mint(token, 2)))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, player, U256::from(1))?, U256::from(2));
+
+        // The transactions are recorded as sent by the session key
+        let sent = Transactions::get_by_sender(&conn, session_key)?;
+        assert_eq!(sent.len(), 2);
+
+        // Out of scope contracts, transaction types and mint limits are rejected
+        let result = insert_transaction(&mut conn, &transaction(
+            session_key, TransactionType::Mint, mint(other_token, 1),
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            session_key,
+            TransactionType::SetDefaultTokenURI,
+            payload::SetDefaultTokenURI { token, uri: "ipfs://evil/".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let result = insert_transaction(&mut conn, &transaction(session_key, TransactionType::Mint, mint(token, 2)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Once expired by block time, the key sends as itself again, without
+        // the signer's roles but free to use MintVM on its own. A client
+        // timestamp before the expiry does not keep it alive
+        insert_block(&conn, 2, 1715136000 + 3600)?;
+        let result = insert_transaction(&mut conn, &transaction(session_key, TransactionType::Mint, mint(token, 1)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(
+            session_key,
+            TransactionType::CreateToken,
+            payload::CreateToken::default(),
+        ))?;
+
+        // As do revoked keys
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::RevokeSessionKey,
+            payload::RevokeSessionKey { sessionKey: session_key.0 },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(session_key, TransactionType::Mint, mint(token, 1)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            session_key,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: signer.0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        // type(uint64).max never expires
+        let lasting_key = address(7);
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::DelegateSessionKey,
+            payload::DelegateSessionKey {
+                sessionKey: lasting_key.0,
+                tokens: vec![token],
+                transactionTypes: vec![TransactionType::Mint.to_string()],
+                maxMints: U256::from(3),
+                expiresAt: u64::MAX,
+            },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            lasting_key,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: signer.0 },
+        ))?;
+        insert_block(&conn, 3, i64::MAX - 1)?;
+        insert_transaction(&mut conn, &transaction(lasting_key, TransactionType::Mint, mint(token, 1)))?;
+
+        Ok(())
+    }
+
+    // Delegates a session key limited to `max_mints` for `types` and has the
+    // key accept it
+    fn delegate_accepted_session_key(
+        conn: &mut Connection,
+        signer: AddressSqlite,
+        session_key: AddressSqlite,
+        token: Address,
+        types: &[TransactionType],
+        max_mints: u64
+    ) -> Result<(), DatabaseError> {
+        insert_transaction(conn, &transaction(
+            signer,
+            TransactionType::DelegateSessionKey,
+            payload::DelegateSessionKey {
+                sessionKey: session_key.0,
+                tokens: vec![token],
+                transactionTypes: types.iter().map(|t| t.to_string()).collect(),
+                maxMints: U256::from(max_mints),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+        insert_transaction(conn, &transaction(
+            session_key,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: signer.0 },
+        ))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_key_mint_limits() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (signer, session_key, player) = (address(1), address(9), address(3));
+        let token = new_token(&mut conn, signer)?;
+        insert_block(&conn, 1, 1715136000)?;
+        delegate_accepted_session_key(&mut conn, signer, session_key, token, &[TransactionType::MintRange], 5)?;
+        let mint_range = |start: u64, quantity: u64| transaction(
+            session_key,
+            TransactionType::MintRange,
+            payload::MintRange { token, to: player.0, startId: U256::from(start), quantity: U256::from(quantity) },
+        );
+
+        // Every id of a range counts against the limit
+        let result = insert_transaction(&mut conn, &mint_range(1, 1000));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &mint_range(1, 4))?;
+        let result = insert_transaction(&mut conn, &mint_range(10, 2));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &mint_range(10, 1))?;
+
+        // MintNext and end-user mints are limited the same way
+        let other_key = address(8);
+        let types = [TransactionType::MintNext, TransactionType::PublicMint];
+        delegate_accepted_session_key(&mut conn, signer, other_key, token, &types, 3)?;
+        let mint_next = |amount: u64| transaction(
+            other_key,
+            TransactionType::MintNext,
+            payload::MintNext { token, to: player.0, amount: U256::from(amount) },
+        );
+        let result = insert_transaction(&mut conn, &mint_next(4));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &mint_next(2))?;
+
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::SetMintPhase,
+            payload::SetMintPhase {
+                token,
+                phase: MintPhase::Public.to_string(),
+                merkleRoot: B256::ZERO,
+                startsAt: 0,
+                endsAt: 0,
+                maxSupply: U256::ZERO,
+                maxPerAddress: U256::ZERO,
+                uniqueIds: false,
+            },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            other_key,
+            TransactionType::PublicMint,
+            payload::PublicMint { token, id: U256::from(100), amount: U256::from(2) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(
+            other_key,
+            TransactionType::PublicMint,
+            payload::PublicMint { token, id: U256::from(100), amount: U256::from(1) },
+        ))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_key_requires_consent() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (signer, victim) = (address(1), address(2));
+        let token = new_token(&mut conn, signer)?;
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::Mint,
+            payload::Mint { token, to: victim.0, id: U256::from(1), amount: U256::from(1) },
+        ))?;
+
+        // Naming someone else's address as a session key does not take over
+        // their transactions
+        insert_transaction(&mut conn, &transaction(
+            signer,
+            TransactionType::DelegateSessionKey,
+            payload::DelegateSessionKey {
+                sessionKey: victim.0,
+                tokens: vec![token],
+                transactionTypes: vec![TransactionType::Mint.to_string()],
+                maxMints: U256::from(3),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            victim,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: victim.0, to: address(3).0, id: U256::from(1), amount: U256::from(1) },
+        ))?;
+        insert_transaction(&mut conn, &transaction(victim, TransactionType::CreateToken, payload::CreateToken::default()))?;
+
+        // An expired delegation can no longer be accepted
+        insert_block(&conn, 2, 1715136000 + 3600)?;
+        let result = insert_transaction(&mut conn, &transaction(
+            victim,
+            TransactionType::AcceptSessionKey,
+            payload::AcceptSessionKey { delegator: signer.0 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::SessionKeyExpired(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_key_requires_signer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let token = new_token(&mut conn, address(1))?;
+
+        let result = insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::DelegateSessionKey,
+            payload::DelegateSessionKey {
+                sessionKey: address(9).0,
+                tokens: vec![token],
+                transactionTypes: vec![TransactionType::Mint.to_string()],
+                maxMints: U256::from(3),
+                expiresAt: 1715136000 + 3600,
+            },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_supply_caps() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let minter = address(1);
+        insert_transaction(&mut conn, &transaction(
+            minter,
+            TransactionType::CreateToken,
+            payload::CreateToken { maxSupply: U256::from(5), maxPerAddress: U256::from(3), ..Default::default() },
+        ))?;
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        let token = contract.address.0;
+        assert_eq!(contract.remaining_supply(), Some(U256::from(5)));
+
+        let mint = |to: AddressSqlite, id: u64, amount: u64| payload::Mint {
+            token, to: to.0, id: U256::from(id), amount: U256::from(amount),
+        };
+        insert_transaction(&mut conn, &transaction(minter, TransactionType::Mint, mint(address(2), 1, 3)))?;
+
+        // The recipient has reached their limit, even for a different id
+        let result = insert_transaction(&mut conn, &transaction(minter, TransactionType::Mint, mint(address(2), 2, 1)));
+        assert!(matches!(result, Err(DatabaseError::MintLimitExceeded(_))));
+
+        insert_transaction(&mut conn, &transaction(minter, TransactionType::Mint, mint(address(3), 2, 2)))?;
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        assert_eq!(contract.remaining_supply(), Some(U256::ZERO));
+        assert_eq!(contract.minted_to(&conn, address(3))?, U256::from(2));
+
+        // Burning does not free up supply
+        insert_transaction(&mut conn, &transaction(
+            address(3),
+            TransactionType::Burn,
+            payload::Burn { token, from: address(3).0, id: U256::from(2), amount: U256::from(2) },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(minter, TransactionType::Mint, mint(address(4), 3, 1)));
+        assert!(matches!(result, Err(DatabaseError::MintLimitExceeded(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uncapped_supply() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let token = new_token(&mut conn, address(1))?;
+
+        insert_transaction(&mut conn, &transaction(
+            address(1),
+            TransactionType::Mint,
+            payload::Mint { token, to: address(2).0, id: U256::ZERO, amount: U256::MAX },
+        ))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.remaining_supply(), None);
+        assert_eq!(contract.total_minted.0, U256::MAX);
+
+        // Malformed CreateToken payloads are rejected
+        let result = insert_transaction(&mut conn, &Transactions {
+            data: b"token".to_vec(),
+            ..transaction(address(1), TransactionType::CreateToken, ())
+        });
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_token_payload_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let key = signing_key(1);
+        let sender = key_address(&key);
+
+        // Payloads encoded before name, symbol and soulbound were added keep
+        // working, with the added fields at their defaults
+        insert_transaction(&mut conn, &transaction(
+            sender,
+            TransactionType::CreateToken,
+            payload::v1::CreateToken { maxSupply: U256::from(5), maxPerAddress: U256::from(3) },
+        ))?;
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        assert_eq!(contract.max_supply.map(|cap| cap.0), Some(U256::from(5)));
+        assert_eq!(contract.max_per_address.map(|cap| cap.0), Some(U256::from(3)));
+        assert!(contract.name.is_empty() && !contract.soulbound);
+
+        insert_transaction(&mut conn, &transaction(
+            sender,
+            TransactionType::CreateToken,
+            payload::v2::CreateToken {
+                maxSupply: U256::from(5),
+                maxPerAddress: U256::ZERO,
+                name: "Swords".to_string(),
+                symbol: "SWD".to_string(),
+            },
+        ))?;
+        let contract = Contracts::get_by_id(&conn, 2)?;
+        assert_eq!(contract.max_supply.map(|cap| cap.0), Some(U256::from(5)));
+        assert_eq!((contract.name.as_str(), contract.symbol.as_str()), ("Swords", "SWD"));
+        assert!(!contract.soulbound);
+
+        insert_transaction(&mut conn, &transaction(
+            sender,
+            TransactionType::CreateToken,
+            payload::CreateToken { name: "Badges".to_string(), soulbound: true, ..Default::default() },
+        ))?;
+        let contract = Contracts::get_by_id(&conn, 3)?;
+        assert_eq!(contract.name, "Badges");
+        assert!(contract.soulbound);
+
+        // Signatures over an earlier version stay valid
+        assert_eq!(
+            transaction_encode_type::<payload::v1::CreateToken>(),
+            "MintVMTransaction(uint256 nonce,CreateToken operation)\
+            CreateToken(uint256 maxSupply,uint256 maxPerAddress)"
+        );
+        let legacy = payload::v1::CreateToken { maxSupply: U256::from(1), maxPerAddress: U256::ZERO };
+        let domain = eip712_domain(DEFAULT_CHAIN_ID, None);
+        let hash = transaction_signing_hash(&domain, 0, &TransactionType::CreateToken, &legacy.abi_encode())?;
+        let mut signed = transaction(sender, TransactionType::CreateToken, legacy);
+        signed.signature = Some(sign_hash(&key, &hash));
+        insert_transaction(&mut conn, &signed)?;
+        assert_eq!(Contracts::get_by_id(&conn, 4)?.max_supply.map(|cap| cap.0), Some(U256::from(1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pause_and_unpause() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (admin, holder) = (address(1), address(2));
+        let token = new_token(&mut conn, admin)?;
+        let mint = payload::Mint { token, to: holder.0, id: U256::from(1), amount: U256::from(2) };
+        insert_transaction(&mut conn, &transaction(admin, TransactionType::Mint, mint.clone()))?;
+
+        // Only admins can pause
+        let result = insert_transaction(&mut conn, &transaction(holder, TransactionType::Pause, payload::Pause { token }));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(admin, TransactionType::Pause, payload::Pause { token }))?;
+        assert!(Contracts::get_by_address(&conn, AddressSqlite::from(token))?.paused);
+
+        // Mints, transfers and approvals are all frozen
+        let transfer = payload::Transfer {
+            token, from: holder.0, to: address(3).0, id: U256::from(1), amount: U256::from(1),
+        };
+        let approval = payload::SetApprovalForAll { token, operator: address(3).0, approved: true };
+        let result = insert_transaction(&mut conn, &transaction(admin, TransactionType::Mint, mint.clone()));
+        assert!(matches!(result, Err(DatabaseError::ContractPaused(paused)) if paused == token));
+        let result = insert_transaction(&mut conn, &transaction(holder, TransactionType::Transfer, transfer.clone()));
+        assert!(matches!(result, Err(DatabaseError::ContractPaused(_))));
+        let result = insert_transaction(&mut conn, &transaction(holder, TransactionType::SetApprovalForAll, approval));
+        assert!(matches!(result, Err(DatabaseError::ContractPaused(_))));
+
+        insert_transaction(&mut conn, &transaction(admin, TransactionType::Unpause, payload::Unpause { token }))?;
+        insert_transaction(&mut conn, &transaction(holder, TransactionType::Transfer, transfer))?;
+        insert_transaction(&mut conn, &transaction(admin, TransactionType::Mint, mint))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ownership_transfer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (studio, new_studio) = (address(1), address(2));
+        let token = new_token(&mut conn, studio)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.owner, studio);
+
+        // Only the owner can nominate, and only the nominee can accept
+        let nominate = payload::TransferOwnership { token, newOwner: new_studio.0 };
+        let result = insert_transaction(&mut conn, &transaction(
+            new_studio, TransactionType::TransferOwnership, nominate.clone(),
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(studio, TransactionType::TransferOwnership, nominate))?;
+
+        let result = insert_transaction(&mut conn, &transaction(
+            address(3), TransactionType::AcceptOwnership, payload::AcceptOwnership { token },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Ownership does not change until accepted
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.owner, studio);
+        assert_eq!(contract.pending_owner, Some(new_studio));
+
+        insert_transaction(&mut conn, &transaction(
+            new_studio, TransactionType::AcceptOwnership, payload::AcceptOwnership { token },
+        ))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.owner, new_studio);
+        assert_eq!(contract.pending_owner, None);
+
+        let history = OwnershipHistory::get_by_contract_id(&conn, contract.id)?;
+        let changes: Vec<_> = history.iter().map(|h| (h.previous_owner, h.new_owner)).collect();
+        assert_eq!(changes, vec![(None, studio), (Some(studio), new_studio)]);
+        // Rejected transactions are rolled back, so the accept is transaction 3
+        assert_eq!(history[1].transaction_id, 3);
+
+        // The roles move with ownership
+        for role in [Role::Admin, Role::Minter, Role::UriManager] {
+            assert!(ContractSigners::has_role(&conn, contract.id, new_studio, role)?);
+        }
+        assert!(!ContractSigners::is_signer(&conn, contract.id, studio)?);
+        let result = insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: studio.0, role: Role::Admin.to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ownership_transfer_requires_multisig() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (studio, cosigner, new_studio) = (address(1), address(2), address(3));
+        let token = new_token(&mut conn, studio)?;
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: cosigner.0 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        let nominate = payload::TransferOwnership { token, newOwner: new_studio.0 };
+        let result = insert_transaction(&mut conn, &transaction(
+            studio, TransactionType::TransferOwnership, nominate.clone(),
+        ));
+        assert!(matches!(result, Err(DatabaseError::MultisigRequired(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::TransferOwnership.to_string(),
+                data: nominate.abi_encode().into(),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            cosigner,
+            TransactionType::ApproveProposal,
+            payload::ApproveProposal { token, proposalId: 1 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            cosigner,
+            TransactionType::ExecuteProposal,
+            payload::ExecuteProposal { token, proposalId: 1 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            new_studio, TransactionType::AcceptOwnership, payload::AcceptOwnership { token },
+        ))?;
+
+        // The new owner fills the seat of the previous one, keeping the
+        // threshold reachable
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.owner, new_studio);
+        assert_eq!(ContractSigners::count_signers(&conn, contract.id)?, 2);
+
+        // Handing ownership to the other signer would leave a single seat
+        let nominate = payload::TransferOwnership { token, newOwner: cosigner.0 };
+        insert_transaction(&mut conn, &transaction(
+            new_studio,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::TransferOwnership.to_string(),
+                data: nominate.abi_encode().into(),
+                expiresAt: 1715136000 + 3600,
+            },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            cosigner,
+            TransactionType::ApproveProposal,
+            payload::ApproveProposal { token, proposalId: 2 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            cosigner,
+            TransactionType::ExecuteProposal,
+            payload::ExecuteProposal { token, proposalId: 2 },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            cosigner, TransactionType::AcceptOwnership, payload::AcceptOwnership { token },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        assert_eq!(Contracts::get_by_id(&conn, contract.id)?.owner, new_studio);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ownership_transfer_keeps_granted_roles() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (studio, new_studio) = (address(1), address(2));
+        let token = new_token(&mut conn, studio)?;
+        insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: studio.0, role: "Clawback".to_string() },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            studio,
+            TransactionType::TransferOwnership,
+            payload::TransferOwnership { token, newOwner: new_studio.0 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            new_studio, TransactionType::AcceptOwnership, payload::AcceptOwnership { token },
+        ))?;
+
+        // Only the roles the creator starts with move to the new owner
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        for role in [Role::Admin, Role::Minter, Role::UriManager] {
+            assert!(!ContractSigners::has_role(&conn, contract.id, studio, role)?);
+            assert!(ContractSigners::has_role(&conn, contract.id, new_studio, role)?);
+        }
+        assert!(ContractSigners::has_role(&conn, contract.id, studio, Role::Clawback)?);
+        assert!(!ContractSigners::has_role(&conn, contract.id, new_studio, Role::Clawback)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_royalties() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (admin, studio, artist) = (address(1), address(2), address(3));
+        let token = new_token(&mut conn, admin)?;
+        let sale_price = U256::from(1_000_000);
+
+        assert_eq!(royalty_info(&conn, token, U256::from(1), sale_price)?, (Address::ZERO, U256::ZERO));
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetDefaultRoyalty,
+            payload::SetDefaultRoyalty { token, receiver: studio.0, basisPoints: 500 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetTokenRoyalty,
+            payload::SetTokenRoyalty {
+                token,
+                id: U256::from(7),
+                receiver: artist.0,
+                basisPoints: 1000,
+            },
+        ))?;
+
+        assert_eq!(royalty_info(&conn, token, U256::from(1), sale_price)?, (studio.0, U256::from(50_000)));
+        assert_eq!(royalty_info(&conn, token, U256::from(7), sale_price)?, (artist.0, U256::from(100_000)));
+
+        // Royalties above 100% are rejected
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetDefaultRoyalty,
+            payload::SetDefaultRoyalty { token, receiver: studio.0, basisPoints: 10_001 },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        // Only admins can set royalties
+        let result = insert_transaction(&mut conn, &transaction(
+            artist,
+            TransactionType::SetTokenRoyalty,
+            payload::SetTokenRoyalty {
+                token,
+                id: U256::from(7),
+                receiver: artist.0,
+                basisPoints: 5000,
+            },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eth_call_royalty_info() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetDefaultRoyalty,
+            payload::SetDefaultRoyalty { token, receiver: admin.0, basisPoints: 250 },
+        ))?;
+
+        let call = IMintVM::royaltyInfoCall { tokenId: U256::from(1), salePrice: U256::from(10_000) };
+        let output = eth_call(&conn, token, &call.abi_encode())?;
+        let decoded = IMintVM::royaltyInfoCall::abi_decode_returns(&output, true)?;
+        assert_eq!(decoded.receiver, admin.0);
+        assert_eq!(decoded.royaltyAmount, U256::from(250));
+
+        let call = IMintVM::supportsInterfaceCall { interfaceId: INTERFACE_ID_ERC2981.into() };
+        let output = eth_call(&conn, token, &call.abi_encode())?;
+        assert!(IMintVM::supportsInterfaceCall::abi_decode_returns(&output, true)?._0);
+
+        // Unknown selectors and non-MintVM addresses are rejected
+        assert!(matches!(eth_call(&conn, token, &[0xde, 0xad, 0xbe, 0xef]), Err(DatabaseError::InvalidCall(_))));
+        assert!(matches!(eth_call(&conn, Address::ZERO, &call.abi_encode()), Err(DatabaseError::UnknownContract(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contract_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::CreateToken,
+            payload::CreateToken { name: "Swords".to_string(), symbol: "SWD".to_string(), ..Default::default() },
+        ))?;
+        let token = Contracts::get_by_id(&conn, 1)?.address.0;
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetContractURI,
+            payload::SetContractURI { token, uri: "ipfs://collection".to_string() },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetContractMetadata,
+            payload::SetContractMetadata {
+                token,
+                name: "Legendary Swords".to_string(),
+                symbol: "LSWD".to_string(),
+                description: "Forged in the game".to_string(),
+            },
+        ))?;
+
+        let contract = Contracts::get_by_id(&conn, 1)?;
+        assert_eq!(contract.name, "Legendary Swords");
+        assert_eq!(contract.description, "Forged in the game");
+        assert_eq!(contract.contract_uri.as_deref(), Some("ipfs://collection"));
+
+        // Every change is kept, starting with the creation
+        let history = ContractMetadataHistory::get_by_contract_id(&conn, contract.id)?;
+        let symbols: Vec<&str> = history.iter().map(|h| h.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["SWD", "SWD", "LSWD"]);
+        assert_eq!(history[1].contract_uri.as_deref(), Some("ipfs://collection"));
+        assert_eq!(history[2].transaction_id, 3);
+
+        let output = eth_call(&conn, token, &IMintVM::contractURICall {}.abi_encode())?;
+        assert_eq!(IMintVM::contractURICall::abi_decode_returns(&output, true)?._0, "ipfs://collection");
+        let output = eth_call(&conn, token, &IMintVM::nameCall {}.abi_encode())?;
+        assert_eq!(IMintVM::nameCall::abi_decode_returns(&output, true)?._0, "Legendary Swords");
+
+        // Only admins can change the collection metadata
+        let result = insert_transaction(&mut conn, &transaction(
+            address(2),
+            TransactionType::SetContractMetadata,
+            payload::SetContractMetadata {
+                token,
+                name: "Rusty Swords".to_string(),
+                symbol: "RUST".to_string(),
+                description: String::new(),
+            },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_freeze_token_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+        let set_default = |uri: &str| transaction(
+            admin,
+            TransactionType::SetDefaultTokenURI,
+            payload::SetDefaultTokenURI { token, uri: uri.to_string() },
+        );
+
+        insert_transaction(&mut conn, &set_default("ipfs://v1/"))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::FreezeMetadata,
+            payload::FreezeMetadata { token, allTokens: false, id: U256::from(1) },
+        ))?;
+
+        // The frozen token keeps the URI it had when frozen
+        insert_transaction(&mut conn, &set_default("ipfs://v2/"))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.token_uri(&conn, U256::from(1))?.as_deref(), Some("ipfs://v1/"));
+        assert_eq!(contract.token_uri(&conn, U256::from(2))?.as_deref(), Some("ipfs://v2/"));
+        assert!(contract.is_token_metadata_frozen(&conn, U256::from(1))?);
+        assert!(!contract.is_token_metadata_frozen(&conn, U256::from(2))?);
+
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetTokenURIPerId,
+            payload::SetTokenURIPerId { token, id: U256::from(1), uri: "ipfs://evil".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::MetadataFrozen(_))));
+
+        let events = Events::get_by_transaction_id(&conn, 3)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "MetadataFrozen");
+        assert_eq!(events[0].decode::<events::MetadataFrozen>()?._tokenId, U256::from(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_freeze_contract_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::FreezeMetadata,
+            payload::FreezeMetadata { token, allTokens: true, id: U256::ZERO },
+        ))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert!(contract.metadata_frozen);
+
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetDefaultTokenURI,
+            payload::SetDefaultTokenURI { token, uri: "ipfs://v2/".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::MetadataFrozen(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetTokenURIPerId,
+            payload::SetTokenURIPerId { token, id: U256::from(5), uri: "ipfs://five".to_string() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::MetadataFrozen(_))));
+
+        let events = Events::get_by_contract_id(&conn, contract.id)?;
+        let frozen = events[0].decode::<events::BatchMetadataFrozen>()?;
+        assert_eq!((frozen._fromTokenId, frozen._toTokenId), (U256::ZERO, U256::MAX));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_soulbound_contract() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let other = address(3);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::CreateToken,
+            payload::CreateToken { soulbound: true, ..Default::default() },
+        ))?;
+        let contract = Contracts::get_by_transaction_id(&conn, 1)?;
+        let token = contract.address.0;
+        let id = U256::from(1);
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: holder.0, id, amount: U256::from(1) },
+        ))?;
+
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: holder.0, to: other.0, id, amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Soulbound(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Approve,
+            payload::Approve { token, owner: holder.0, spender: other.0, id },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Soulbound(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::SetApprovalForAll,
+            payload::SetApprovalForAll { token, operator: other.0, approved: true },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Soulbound(_))));
+
+        // Only the holder or an admin can burn a soulbound token
+        let burn = payload::Burn { token, from: holder.0, id, amount: U256::from(1) };
+        let result = insert_transaction(&mut conn, &transaction(other, TransactionType::Burn, burn.clone()));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(admin, TransactionType::Burn, burn))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, id)?, U256::ZERO);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_soulbound_token() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let other = address(3);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let badge = U256::from(1);
+        let item = U256::from(2);
+
+        for id in [badge, item] {
+            insert_transaction(&mut conn, &transaction(
+                admin,
+                TransactionType::Mint,
+                payload::Mint { token, to: holder.0, id, amount: U256::from(1) },
+            ))?;
+        }
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::MarkSoulbound,
+            payload::MarkSoulbound { token, id: badge },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::MarkSoulbound,
+            payload::MarkSoulbound { token, id: badge },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Soulbound(_))));
+
+        let move_token = |id: U256| payload::Transfer { token, from: holder.0, to: other.0, id, amount: U256::from(1) };
+        let result = insert_transaction(&mut conn, &transaction(holder, TransactionType::Transfer, move_token(badge)));
+        assert!(matches!(result, Err(DatabaseError::Soulbound(_))));
+        insert_transaction(&mut conn, &transaction(holder, TransactionType::Transfer, move_token(item)))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, other, item)?, U256::from(1));
+
+        let events = Events::get_by_contract_id(&conn, contract.id)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].decode::<events::Locked>()?.tokenId, badge);
+
+        // ERC-5192 locked() reports the marked id only
+        for (id, locked) in [(badge, true), (item, false)] {
+            let output = eth_call(&conn, token, &IMintVM::lockedCall { tokenId: id }.abi_encode())?;
+            assert_eq!(IMintVM::lockedCall::abi_decode_returns(&output, true)?._0, locked);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mint_range() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let other = address(3);
+        let token = new_token(&mut conn, admin)?;
+        let mint_range = |start: u64, quantity: u64| transaction(
+            admin,
+            TransactionType::MintRange,
+            payload::MintRange { token, to: holder.0, startId: U256::from(start), quantity: U256::from(quantity) },
+        );
+
+        insert_transaction(&mut conn, &mint_range(1, 100_000))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.total_minted, U256Sqlite::from(U256::from(100_000)));
+        assert_eq!(contract.owner_of(&conn, U256::from(50_000))?, Some(holder));
+        assert_eq!(contract.owner_of(&conn, U256::from(100_001))?, None);
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, U256::from(100_000))?, U256::from(1));
+
+        // Overlapping ranges and single mints into a range are rejected
+        for (start, quantity) in [(0, 2), (100_000, 5), (10, 1)] {
+            let result = insert_transaction(&mut conn, &mint_range(start, quantity));
+            assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        }
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: other.0, id: U256::from(7), amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        // Transferring an id splits it out of the range
+        let id = U256::from(50_000);
+        insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: holder.0, to: other.0, id, amount: U256::from(1) },
+        ))?;
+        assert_eq!(contract.owner_of(&conn, id)?, Some(other));
+        assert_eq!(Balances::balance_of(&conn, contract.id, holder, id)?, U256::ZERO);
+        let ranges = TokenRanges::get_by_owner(&conn, holder)?;
+        assert_eq!(ranges.len(), 2);
+        assert_eq!((ranges[0].start_id.0, ranges[0].end_id.0), (U256::from(1), U256::from(49_999)));
+        assert_eq!((ranges[1].start_id.0, ranges[1].end_id.0), (U256::from(50_001), U256::from(100_000)));
+        assert_eq!(ranges[0].len() + ranges[1].len(), U256::from(99_999));
+
+        // Burning the first id shrinks the range from the start
+        insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Burn,
+            payload::Burn { token, from: holder.0, id: U256::from(1), amount: U256::from(1) },
+        ))?;
+        assert_eq!(contract.owner_of(&conn, U256::from(1))?, None);
+        assert_eq!(TokenRanges::get_by_owner(&conn, holder)?[0].start_id.0, U256::from(2));
+
+        assert_uses_index(
+            &conn,
+            "SELECT * FROM token_ranges WHERE contract_id = 1 AND start_id <= x'00'
+            ORDER BY start_id DESC LIMIT 1",
+            &["sqlite_autoindex_token_ranges_1"],
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mint_next() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let token = new_token(&mut conn, admin)?;
+        let mint_next = transaction(
+            admin,
+            TransactionType::MintNext,
+            payload::MintNext { token, to: holder.0, amount: U256::from(1) },
+        );
+
+        // Ids minted explicitly or as a range are skipped
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: holder.0, id: U256::from(2), amount: U256::from(1) },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::MintRange,
+            payload::MintRange { token, to: holder.0, startId: U256::from(4), quantity: U256::from(3) },
+        ))?;
+
+        let mut allocated = Vec::new();
+        for _ in 0..3 {
+            let receipt = insert_transaction(&mut conn, &mint_next)?;
+            assert_eq!(Receipts::get_by_transaction_id(&conn, receipt.transaction_id)?, receipt);
+            allocated.push(receipt.token_id.map(|id| id.0));
+        }
+        assert_eq!(allocated, [1, 3, 7].map(|id| Some(U256::from(id))));
+
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(contract.owner_of(&conn, U256::from(7))?, Some(holder));
+        assert_eq!(contract.next_token_id.0, U256::from(8));
+
+        // Rejected mints do not use up an id
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::MintNext,
+            payload::MintNext { token, to: holder.0, amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let receipt = insert_transaction(&mut conn, &mint_next)?;
+        assert_eq!(receipt.token_id, Some(U256Sqlite::from(U256::from(8))));
+
+        // Burned ids are never handed out again, even after every holder
+        // burned theirs
+        let edition = U256::from(9);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: holder.0, id: edition, amount: U256::from(2) },
+        ))?;
+        for _ in 0..2 {
+            insert_transaction(&mut conn, &transaction(
+                holder,
+                TransactionType::Burn,
+                payload::Burn { token, from: holder.0, id: edition, amount: U256::from(1) },
+            ))?;
+        }
+        let receipt = insert_transaction(&mut conn, &mint_next)?;
+        assert_eq!(receipt.token_id, Some(U256Sqlite::from(U256::from(10))));
+
+        // Other transactions carry no token id
+        assert_eq!(Receipts::get_by_transaction_id(&conn, 1)?.token_id, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redeem_voucher() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let minter = signing_key(1);
+        let player = address(2);
+        let token = new_token(&mut conn, key_address(&minter))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let voucher = |to: Address, nonce: u64| payload::MintVoucher {
+            token,
+            to,
+            id: U256::from(7),
+            amount: U256::from(1),
+            expiresAt: 1715136000 + 3600,
+            nonce: U256::from(nonce),
+        };
+        let redeem = |sender: AddressSqlite, voucher: payload::MintVoucher, key: &k256::ecdsa::SigningKey| {
+            let signature = sign_typed(key, token, &voucher).into();
+            transaction(sender, TransactionType::RedeemVoucher, payload::RedeemVoucher { voucher, signature })
+        };
+
+        // Anyone can submit a voucher bound to a recipient. Expiry is checked
+        // against block time, not against the timestamp the submitter sends
+        let late = Transactions { timestamp: i64::MAX, ..redeem(address(9), voucher(player.0, 1), &minter) };
+        insert_transaction(&mut conn, &late)?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, player, U256::from(7))?, U256::from(1));
+
+        let result = insert_transaction(&mut conn, &redeem(player, voucher(player.0, 1), &minter));
+        assert!(matches!(result, Err(DatabaseError::VoucherRedeemed(_))));
+
+        // Open vouchers mint to whoever redeems them
+        insert_transaction(&mut conn, &redeem(address(3), voucher(Address::ZERO, 2), &minter))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, address(3), U256::from(7))?, U256::from(1));
+
+        // Vouchers signed by non-minters, tampered with or expired are rejected
+        let result = insert_transaction(&mut conn, &redeem(player, voucher(player.0, 3), &signing_key(2)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        let signature = sign_typed(&minter, token, &voucher(player.0, 3)).into();
+        let tampered = payload::MintVoucher { amount: U256::from(100), ..voucher(player.0, 3) };
+        let result = insert_transaction(&mut conn, &transaction(
+            player,
+            TransactionType::RedeemVoucher,
+            payload::RedeemVoucher { voucher: tampered, signature },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        insert_block(&conn, 1, 1715136000)?;
+        let expired = payload::MintVoucher { expiresAt: 1715136000, ..voucher(player.0, 3) };
+        let result = insert_transaction(&mut conn, &redeem(player, expired, &minter));
+        assert!(matches!(result, Err(DatabaseError::VoucherExpired(_))));
+        let unexpiring = payload::MintVoucher { expiresAt: u64::MAX, ..voucher(player.0, 4) };
+        insert_transaction(&mut conn, &redeem(player, unexpiring, &minter))?;
+
+        let result = insert_transaction(&mut conn, &transaction(
+            player,
+            TransactionType::RedeemVoucher,
+            payload::RedeemVoucher { voucher: voucher(player.0, 3), signature: vec![0u8; 10].into() },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_transactions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let key = signing_key(1);
+        let sender = key_address(&key);
+        let token = new_token(&mut conn, sender)?;
+        let mint = payload::Mint { token, to: sender.0, id: U256::from(1), amount: U256::from(1) };
+        let signed = |chain_id: u64, nonce: u64, key: &k256::ecdsa::SigningKey| {
+            let mut tx = transaction(sender, TransactionType::Mint, mint.clone());
+            let domain = eip712_domain(chain_id, Some(token));
+            let hash = transaction_signing_hash(&domain, nonce, &tx.transaction_type, &tx.data).unwrap();
+            tx.signature = Some(sign_hash(key, &hash));
+            tx
         };
-        insert_transaction(&mut conn, &transaction)?;
 
-        // Use getter instead of direct row access
-        let saved_transaction = Transactions::get_by_id(&conn, 1)?;
-        
-        assert_eq!(saved_transaction.id, 1); // First record should have ID 1
-        assert_eq!(saved_transaction.sender, sender);
-        assert_eq!(saved_transaction.transaction_type, TransactionType::CreateToken);
-        assert_eq!(saved_transaction.data, test_data);
-        assert_eq!(saved_transaction.timestamp, test_timestamp);
+        assert_eq!(
+            transaction_encode_type::<payload::Mint>(),
+            "MintVMTransaction(uint256 nonce,Mint operation)Mint(address token,address to,uint256 id,uint256 amount)"
+        );
+        assert_eq!(
+            transaction_encode_type::<payload::RedeemVoucher>(),
+            "MintVMTransaction(uint256 nonce,RedeemVoucher operation)\
+            MintVoucher(address token,address to,uint256 id,uint256 amount,uint64 expiresAt,uint256 nonce)\
+            RedeemVoucher(MintVoucher voucher,bytes signature)"
+        );
 
-        // Verify contract was created
-        let contract = Contracts::get_by_id(&conn, 1)?;
-        assert_eq!(contract.transaction_id, 1);
-        assert_eq!(contract.signers.0, vec![sender]); // Verify sender is set as initial signer
+        let receipt = insert_transaction(&mut conn, &signed(DEFAULT_CHAIN_ID, 0, &key))?;
+        assert!(Transactions::get_by_id(&conn, receipt.transaction_id)?.signature.is_some());
+        assert_eq!(sender_nonce(&conn, sender)?, 1);
+
+        // Replays, other chains and other signers are rejected
+        let result = insert_transaction(&mut conn, &signed(DEFAULT_CHAIN_ID, 0, &key));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        let result = insert_transaction(&mut conn, &signed(1, 1, &key));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        let result = insert_transaction(&mut conn, &signed(DEFAULT_CHAIN_ID, 1, &signing_key(2)));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        assert_eq!(sender_nonce(&conn, sender)?, 1);
+
+        insert_transaction(&mut conn, &signed(DEFAULT_CHAIN_ID, 1, &key))?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, sender, U256::from(1))?, U256::from(2));
+
+        // A signature for the default chain is not valid on another instance
+        let mut testnet = initialize_db_with_chain_id(11155111)?;
+        new_token(&mut testnet, sender)?;
+        let result = insert_transaction(&mut testnet, &signed(DEFAULT_CHAIN_ID, 0, &key));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        insert_transaction(&mut testnet, &signed(11155111, 0, &key))?;
 
         Ok(())
     }
 
     #[test]
-    fn test_get_contract() -> Result<(), Box<dyn std::error::Error>> {
+    fn test_meta_transactions() -> Result<(), Box<dyn std::error::Error>> {
         let mut conn = initialize_db()?;
-        let sender = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
-        
-        // First insert a transaction that will create a contract
-        let transaction = Transactions {
-            id: 0,
-            sender,
-            transaction_type: TransactionType::CreateToken,
-            data: "0x".as_bytes().to_vec(),
-            timestamp: 1715136000,
+        let admin = address(1);
+        let relayer_key = signing_key(9);
+        let relayer = key_address(&relayer_key);
+        let player_key = signing_key(2);
+        let player = key_address(&player_key);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: player.0, id: U256::from(1), amount: U256::from(5) },
+        ))?;
+
+        // The player signs a transfer, and the relayer signs and submits it
+        let relayed_by = |nonce: u64, key: &k256::ecdsa::SigningKey, relayer_key: &k256::ecdsa::SigningKey| {
+            let data = payload::Transfer {
+                token, from: player.0, to: admin.0, id: U256::from(1), amount: U256::from(1),
+            }.abi_encode();
+            let domain = eip712_domain(DEFAULT_CHAIN_ID, Some(token));
+            let hash = transaction_signing_hash(&domain, nonce, &TransactionType::Transfer, &data).unwrap();
+            MetaTransaction {
+                relayer,
+                transaction_type: TransactionType::Transfer,
+                data,
+                nonce,
+                signature: sign_hash(key, &hash),
+                relayer_signature: sign_hash(relayer_key, &hash),
+                timestamp: 1715136000,
+            }
         };
-        insert_transaction(&mut conn, &transaction)?;
+        let meta = |nonce: u64, key: &k256::ecdsa::SigningKey| relayed_by(nonce, key, &relayer_key);
 
-        // Get contract by ID
-        let contract = Contracts::get_by_id(&conn, 1)?;
-        assert_eq!(contract.id, 1);
-        assert_eq!(contract.transaction_id, 1);
-        assert_eq!(contract.signers.0, vec![sender]);
+        // Relayers must be allowed by the contract first
+        let result = insert_meta_transaction(&mut conn, &meta(0, &player_key));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetRelayer,
+            payload::SetRelayer { token, relayer: relayer.0, allowed: true },
+        ))?;
 
-        // Get contract by address and verify it matches
-        let contract_by_addr = Contracts::get_by_address(&conn, contract.address)?;
-        assert_eq!(contract_by_addr.id, contract.id);
-        assert_eq!(contract_by_addr.address, contract.address);
-        assert_eq!(contract_by_addr.signers.0, contract.signers.0);
-        assert_eq!(contract_by_addr.transaction_id, contract.transaction_id);
+        let receipt = insert_meta_transaction(&mut conn, &meta(0, &player_key))?;
+        let saved = Transactions::get_by_id(&conn, receipt.transaction_id)?;
+        assert_eq!(saved.sender, player);
+        assert_eq!(saved.relayer, Some(relayer));
+        assert!(saved.relayer_signature.is_some());
+        assert_eq!(Balances::balance_of(&conn, contract.id, player, U256::from(1))?, U256::from(4));
+        assert_eq!(Transactions::get_by_relayer(&conn, relayer)?.len(), 1);
 
-        // Get contract by transaction ID and verify it matches
-        let contract_by_tx = Contracts::get_by_transaction_id(&conn, 1)?;
-        assert_eq!(contract_by_tx.id, contract.id);
-        assert_eq!(contract_by_tx.address, contract.address);
-        assert_eq!(contract_by_tx.signers.0, contract.signers.0);
-        assert_eq!(contract_by_tx.transaction_id, contract.transaction_id);
+        // Replayed nonces are rejected, and a relayer cannot move tokens of
+        // someone who did not sign
+        let result = insert_meta_transaction(&mut conn, &meta(0, &player_key));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        let result = insert_meta_transaction(&mut conn, &meta(0, &signing_key(3)));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Nor can anyone claim to be an allowed relayer without its key
+        let result = insert_meta_transaction(&mut conn, &relayed_by(1, &player_key, &signing_key(3)));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+        let result = insert_meta_transaction(&mut conn, &MetaTransaction {
+            relayer_signature: Vec::new(),
+            ..meta(1, &player_key)
+        });
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+
+        // Relayed transactions must carry the sender's signature
+        let result = insert_transaction(&mut conn, &Transactions {
+            relayer: Some(relayer),
+            ..transaction(player, TransactionType::Burn, payload::Burn {
+                token, from: player.0, id: U256::from(1), amount: U256::from(1),
+            })
+        });
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetRelayer,
+            payload::SetRelayer { token, relayer: relayer.0, allowed: false },
+        ))?;
+        let result = insert_meta_transaction(&mut conn, &meta(1, &player_key));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
 
         Ok(())
     }
 
+    fn hash_pair(a: B256, b: B256) -> B256 {
+        if a <= b { keccak256([a, b].concat()) } else { keccak256([b, a].concat()) }
+    }
+
     #[test]
-    fn test_multiple_transactions() -> Result<(), Box<dyn std::error::Error>> {
+    fn test_allowlist_and_public_phases() -> Result<(), Box<dyn std::error::Error>> {
         let mut conn = initialize_db()?;
-        
-        // Create test data
-        let sender1 = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000001").unwrap());
-        let sender2 = AddressSqlite::from(Address::from_str("0x0000000000000000000000000000000000000002").unwrap());
-        
-        let test_transactions = vec![
-            Transactions {
-                id: 0,
-                sender: sender1,
-                transaction_type: TransactionType::CreateToken,
-                data: b"token1".to_vec(),
-                timestamp: 1000,
-            },
-            Transactions {
-                id: 0,
-                sender: sender1,
-                transaction_type: TransactionType::Mint,
-                data: b"mint1".to_vec(),
-                timestamp: 1001,
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let leaf = |account: AddressSqlite, id: u64, quantity: u64| {
+            keccak256(keccak256((account.0, U256::from(id), U256::from(quantity)).abi_encode()))
+        };
+        let leaves = [leaf(address(2), 1, 2), leaf(address(3), 2, 1), leaf(address(4), 3, 5)];
+        let root = hash_pair(hash_pair(leaves[0], leaves[1]), leaves[2]);
+        let claim = |sender: AddressSqlite, id: u64, amount: u64, quantity: u64, proof: Vec<B256>| transaction(
+            sender,
+            TransactionType::ClaimAllowlist,
+            payload::ClaimAllowlist {
+                token,
+                id: U256::from(id),
+                amount: U256::from(amount),
+                quantity: U256::from(quantity),
+                proof,
             },
-            Transactions {
-                id: 0,
-                sender: sender2,
-                transaction_type: TransactionType::CreateToken,
-                data: b"token2".to_vec(),
-                timestamp: 1002,
+        );
+        let proof = vec![leaves[1], leaves[2]];
+
+        let result = insert_transaction(&mut conn, &claim(address(2), 1, 1, 2, proof.clone()));
+        assert!(matches!(result, Err(DatabaseError::MintPhaseClosed(_))));
+
+        // The allowlist phase runs for 100 seconds, then the public phase
+        // opens. Both mint unique ids, as for an ERC-721 drop
+        let set_phase = |phase: MintPhase, merkle_root: B256, starts_at: u64, ends_at: u64| transaction(
+            admin,
+            TransactionType::SetMintPhase,
+            payload::SetMintPhase {
+                token,
+                phase: phase.to_string(),
+                merkleRoot: merkle_root,
+                startsAt: starts_at,
+                endsAt: ends_at,
+                maxSupply: U256::ZERO,
+                maxPerAddress: U256::ZERO,
+                uniqueIds: true,
             },
-            Transactions {
-                id: 0,
-                sender: sender2,
-                transaction_type: TransactionType::Transfer,
-                data: b"transfer1".to_vec(),
-                timestamp: 1003,
+        );
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &set_phase(MintPhase::Allowlist, root, 1715136000, 1715136100))?;
+        insert_transaction(&mut conn, &set_phase(MintPhase::Public, B256::ZERO, 1715136100, 0))?;
+
+        // Leaves can be claimed in parts up to their quantity
+        insert_transaction(&mut conn, &claim(address(2), 1, 1, 2, proof.clone()))?;
+        insert_transaction(&mut conn, &claim(address(2), 1, 1, 2, proof.clone()))?;
+        let result = insert_transaction(&mut conn, &claim(address(2), 1, 1, 2, proof.clone()));
+        assert!(matches!(result, Err(DatabaseError::MintLimitExceeded(_))));
+        assert_eq!(Balances::balance_of(&conn, contract.id, address(2), U256::from(1))?, U256::from(2));
+
+        // Proofs for another account or quantity are rejected
+        let result = insert_transaction(&mut conn, &claim(address(5), 1, 1, 2, proof.clone()));
+        assert!(matches!(result, Err(DatabaseError::InvalidMerkleProof(_))));
+        let result = insert_transaction(&mut conn, &claim(address(3), 2, 2, 2, vec![leaves[0], leaves[2]]));
+        assert!(matches!(result, Err(DatabaseError::InvalidMerkleProof(_))));
+
+        // A leaf cannot mint an id that is already held
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: admin.0, id: U256::from(3), amount: U256::from(1) },
+        ))?;
+        let result = insert_transaction(&mut conn, &claim(address(4), 3, 5, 5, vec![hash_pair(leaves[0], leaves[1])]));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        let public_mint = |id: u64| transaction(
+            address(5),
+            TransactionType::PublicMint,
+            payload::PublicMint { token, id: U256::from(id), amount: U256::from(1) },
+        );
+        let result = insert_transaction(&mut conn, &public_mint(10));
+        assert!(matches!(result, Err(DatabaseError::MintPhaseClosed(_))));
+
+        insert_block(&conn, 2, 1715136100)?;
+        insert_transaction(&mut conn, &public_mint(10))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, address(5), U256::from(10))?, U256::from(1));
+        let result = insert_transaction(&mut conn, &claim(address(3), 2, 1, 1, vec![leaves[0], leaves[2]]));
+        assert!(matches!(result, Err(DatabaseError::MintPhaseClosed(_))));
+
+        // Public mints cannot add to held ids or ids covered by a range
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::MintRange,
+            payload::MintRange { token, to: admin.0, startId: U256::from(20), quantity: U256::from(5) },
+        ))?;
+        for id in [1, 10, 22] {
+            let result = insert_transaction(&mut conn, &public_mint(id));
+            assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edition_mint_phases() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let edition = U256::from(5);
+        let leaves = [address(2), address(3)]
+            .map(|account| keccak256(keccak256((account.0, edition, U256::from(1)).abi_encode())));
+        let set_phase = |phase: MintPhase, merkle_root: B256| payload::SetMintPhase {
+            token,
+            phase: phase.to_string(),
+            merkleRoot: merkle_root,
+            startsAt: 0,
+            endsAt: 0,
+            maxSupply: U256::ZERO,
+            maxPerAddress: U256::ZERO,
+            uniqueIds: false,
+        };
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetMintPhase,
+            set_phase(MintPhase::Allowlist, hash_pair(leaves[0], leaves[1])),
+        ))?;
+
+        // Payloads encoded before uniqueIds was added leave it unset
+        let public = set_phase(MintPhase::Public, B256::ZERO);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetMintPhase,
+            payload::v1::SetMintPhase {
+                token: public.token,
+                phase: public.phase,
+                merkleRoot: public.merkleRoot,
+                startsAt: public.startsAt,
+                endsAt: public.endsAt,
+                maxSupply: public.maxSupply,
+                maxPerAddress: public.maxPerAddress,
             },
-        ];
+        ))?;
+        assert!(!MintPhases::get(&conn, contract.id, MintPhase::Public)?.unwrap().unique_ids);
 
-        // Insert all transactions
-        for tx in &test_transactions {
-            insert_transaction(&mut conn, tx)?;
+        // Without unique ids, every allowlisted account and public minter
+        // gets a copy of the same edition, and the ERC-20 id can be minted
+        for (sender, proof) in [(address(2), leaves[1]), (address(3), leaves[0])] {
+            insert_transaction(&mut conn, &transaction(
+                sender,
+                TransactionType::ClaimAllowlist,
+                payload::ClaimAllowlist {
+                    token,
+                    id: edition,
+                    amount: U256::from(1),
+                    quantity: U256::from(1),
+                    proof: vec![proof],
+                },
+            ))?;
         }
+        for sender in [address(4), address(5)] {
+            for id in [edition, ERC20_TOKEN_ID] {
+                insert_transaction(&mut conn, &transaction(
+                    sender,
+                    TransactionType::PublicMint,
+                    payload::PublicMint { token, id, amount: U256::from(1) },
+                ))?;
+            }
+        }
+        for holder in [address(2), address(3), address(4), address(5)] {
+            assert_eq!(Balances::balance_of(&conn, contract.id, holder, edition)?, U256::from(1));
+        }
+        assert_eq!(Balances::balance_of(&conn, contract.id, address(5), ERC20_TOKEN_ID)?, U256::from(1));
 
-        // Test different query methods
-        
-        // 1. Get all CreateToken transactions
-        let create_txs = Transactions::get_by_type(&conn, TransactionType::CreateToken)?;
-        assert_eq!(create_txs.len(), 2);
-        assert!(create_txs.iter().all(|tx| tx.transaction_type == TransactionType::CreateToken));
+        Ok(())
+    }
 
-        // 2. Get all transactions from sender1
-        let sender1_txs = Transactions::get_by_sender(&conn, sender1)?;
-        assert_eq!(sender1_txs.len(), 2);
-        assert!(sender1_txs.iter().all(|tx| tx.sender == sender1));
+    #[test]
+    fn test_mint_phases_use_block_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let token = new_token(&mut conn, admin)?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetMintPhase,
+            payload::SetMintPhase {
+                token,
+                phase: MintPhase::Public.to_string(),
+                merkleRoot: B256::ZERO,
+                startsAt: 2000,
+                endsAt: 3000,
+                maxSupply: U256::from(3),
+                maxPerAddress: U256::from(2),
+                uniqueIds: false,
+            },
+        ))?;
+        let public_mint = |sender: AddressSqlite, id: u64, amount: u64| transaction(
+            sender,
+            TransactionType::PublicMint,
+            payload::PublicMint { token, id: U256::from(id), amount: U256::from(amount) },
+        );
 
-        // 3. Get CreateToken transactions from sender2
-        let sender2_create_txs = Transactions::get_by_type_and_sender(
-            &conn,
-            TransactionType::CreateToken,
-            sender2
-        )?;
-        assert_eq!(sender2_create_txs.len(), 1);
-        assert_eq!(sender2_create_txs[0].data, b"token2");
+        // The client-supplied timestamp is inside the window, but no block is
+        let early = Transactions { timestamp: 2500, ..public_mint(address(2), 1, 1) };
+        assert!(matches!(insert_transaction(&mut conn, &early), Err(DatabaseError::MintPhaseClosed(_))));
+        insert_block(&conn, 1, 1999)?;
+        assert!(matches!(insert_transaction(&mut conn, &early), Err(DatabaseError::MintPhaseClosed(_))));
 
-        // 4. Get transactions after timestamp 1001
-        let recent_txs = Transactions::get_by_type_after_timestamp(
-            &conn,
+        insert_block(&conn, 2, 2000)?;
+        let receipt = insert_transaction(&mut conn, &public_mint(address(2), 1, 2))?;
+        assert_eq!(Transactions::get_by_id(&conn, receipt.transaction_id)?.block_number, Some(2));
+
+        // Per-phase limits apply per recipient and to the phase as a whole
+        let result = insert_transaction(&mut conn, &public_mint(address(2), 2, 1));
+        assert!(matches!(result, Err(DatabaseError::MintLimitExceeded(_))));
+        insert_transaction(&mut conn, &public_mint(address(3), 3, 1))?;
+        let result = insert_transaction(&mut conn, &public_mint(address(4), 4, 1));
+        assert!(matches!(result, Err(DatabaseError::MintLimitExceeded(_))));
+        let window = MintPhases::get(&conn, 1, MintPhase::Public)?.unwrap();
+        assert_eq!(window.minted.0, U256::from(3));
+
+        insert_block(&conn, 3, 3000)?;
+        let result = insert_transaction(&mut conn, &public_mint(address(4), 5, 1));
+        assert!(matches!(result, Err(DatabaseError::MintPhaseClosed(_))));
+
+        // A phase starting past i64::MAX never opens
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetMintPhase,
+            payload::SetMintPhase {
+                token,
+                phase: MintPhase::Public.to_string(),
+                merkleRoot: B256::ZERO,
+                startsAt: u64::MAX,
+                endsAt: 0,
+                maxSupply: U256::ZERO,
+                maxPerAddress: U256::ZERO,
+                uniqueIds: false,
+            },
+        ))?;
+        let result = insert_transaction(&mut conn, &public_mint(address(4), 6, 1));
+        assert!(matches!(result, Err(DatabaseError::MintPhaseClosed(_))));
+
+        // Blocks cannot go back in number or time
+        assert!(matches!(insert_block(&conn, 3, 3001), Err(DatabaseError::InvalidBlock(_))));
+        assert!(matches!(insert_block(&conn, 4, 2999), Err(DatabaseError::InvalidBlock(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_erc20_allowances() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let player = address(2);
+        let shop = address(3);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: player.0, id: ERC20_TOKEN_ID, amount: U256::from(100) },
+        ))?;
+        let set_allowance = |amount: U256| transaction(
+            player,
+            TransactionType::SetAllowance,
+            payload::SetAllowance { token, spender: shop.0, amount },
+        );
+        let spend = |amount: u64| transaction(
+            shop,
+            TransactionType::TransferFrom,
+            payload::TransferFrom { token, from: player.0, to: shop.0, amount: U256::from(amount) },
+        );
+
+        insert_transaction(&mut conn, &set_allowance(U256::from(30)))?;
+        insert_transaction(&mut conn, &spend(20))?;
+        assert_eq!(Allowances::allowance(&conn, contract.id, player, shop)?, U256::from(10));
+        let result = insert_transaction(&mut conn, &spend(20));
+        assert!(matches!(result, Err(DatabaseError::InsufficientAllowance(_))));
+
+        // NFT approvals do not grant an allowance
+        insert_transaction(&mut conn, &transaction(
+            player,
+            TransactionType::SetApprovalForAll,
+            payload::SetApprovalForAll { token, operator: address(4).0, approved: true },
+        ))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            address(4),
+            TransactionType::TransferFrom,
+            payload::TransferFrom { token, from: player.0, to: address(4).0, amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::InsufficientAllowance(_))));
+
+        // Infinite allowances are never decremented
+        insert_transaction(&mut conn, &set_allowance(U256::MAX))?;
+        insert_transaction(&mut conn, &spend(50))?;
+        assert_eq!(Allowances::allowance(&conn, contract.id, player, shop)?, U256::MAX);
+        let result = insert_transaction(&mut conn, &spend(50));
+        assert!(matches!(result, Err(DatabaseError::InsufficientBalance(_))));
+
+        let call = IMintVM::allowanceCall { owner: player.0, spender: shop.0 };
+        let output = eth_call(&conn, token, &call.abi_encode())?;
+        assert_eq!(IMintVM::allowanceCall::abi_decode_returns(&output, true)?._0, U256::MAX);
+        let output = eth_call(&conn, token, &IMintVM::balanceOfCall { account: shop.0 }.abi_encode())?;
+        assert_eq!(IMintVM::balanceOfCall::abi_decode_returns(&output, true)?._0, U256::from(70));
+
+        insert_transaction(&mut conn, &set_allowance(U256::ZERO))?;
+        assert!(Allowances::get_by_owner(&conn, contract.id, player)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permit() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let owner_key = signing_key(2);
+        let owner = key_address(&owner_key);
+        let spender = address(3);
+        insert_transaction(&mut conn, &transaction(
+            admin,
             TransactionType::CreateToken,
-            1001
-        )?;
-        assert_eq!(recent_txs.len(), 1);
-        assert_eq!(recent_txs[0].sender, sender2);
+            payload::CreateToken { name: "Gold".to_string(), symbol: "GLD".to_string(), ..Default::default() },
+        ))?;
+        let contract = Contracts::get_by_transaction_id(&conn, 1)?;
+        let token = contract.address.0;
 
-        // 5. Verify we can get each transaction by ID
-        for i in 1..=4 {
-            let tx = Transactions::get_by_id(&conn, i)?;
-            println!("Transaction {}: {:?}", i, tx);
+        // The domain a dapp would build from name() and the chain id
+        let domain = alloy::sol_types::eip712_domain! {
+            name: "Gold",
+            version: "1",
+            chain_id: DEFAULT_CHAIN_ID,
+            verifying_contract: token,
+        };
+        let output = eth_call(&conn, token, &IMintVM::DOMAIN_SEPARATORCall {}.abi_encode())?;
+        assert_eq!(IMintVM::DOMAIN_SEPARATORCall::abi_decode_returns(&output, true)?._0, domain.separator());
+
+        let permit = |nonce: u64, deadline: u64| {
+            let typed = typed_data::Permit {
+                owner: owner.0,
+                spender: spender.0,
+                value: U256::from(500),
+                nonce: U256::from(nonce),
+                deadline: U256::from(deadline),
+            };
+            let signature = PrimitiveSignature::from_raw(&sign_hash(&owner_key, &typed.eip712_signing_hash(&domain)))
+                .unwrap();
+            transaction(address(9), TransactionType::Permit, payload::Permit {
+                token,
+                owner: owner.0,
+                spender: spender.0,
+                value: U256::from(500),
+                deadline: U256::from(deadline),
+                v: 27 + signature.v() as u8,
+                r: signature.r().into(),
+                s: signature.s().into(),
+            })
+        };
+
+        // Deadlines are checked against block time only, whatever timestamp
+        // the submitter sends. Before the first block, block time is the
+        // genesis time, so even a deadline of 0 has not passed
+        insert_transaction(&mut conn, &Transactions { timestamp: i64::MAX, ..permit(0, 0) })?;
+        assert_eq!(Allowances::allowance(&conn, contract.id, owner, spender)?, U256::from(500));
+
+        insert_block(&conn, 1, 1715136000)?;
+        let result = insert_transaction(&mut conn, &Transactions { timestamp: -1, ..permit(1, 1715135999) });
+        assert!(matches!(result, Err(DatabaseError::PermitExpired(_))));
+
+        insert_transaction(&mut conn, &permit(1, 1715136000))?;
+        let output = eth_call(&conn, token, &IMintVM::noncesCall { owner: owner.0 }.abi_encode())?;
+        assert_eq!(IMintVM::noncesCall::abi_decode_returns(&output, true)?._0, U256::from(2));
+
+        // The signature cannot be replayed once its nonce is used
+        let result = insert_transaction(&mut conn, &permit(1, 1715136000));
+        assert!(matches!(result, Err(DatabaseError::InvalidSignature(_))));
+
+        // Renaming the collection leaves the domain, and so signed permits,
+        // as they were
+        let renamed = permit(2, 1715136000);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetContractMetadata,
+            payload::SetContractMetadata {
+                token,
+                name: "Gold v2".to_string(),
+                symbol: "GLD".to_string(),
+                description: String::new(),
+            },
+        ))?;
+        let output = eth_call(&conn, token, &IMintVM::DOMAIN_SEPARATORCall {}.abi_encode())?;
+        assert_eq!(IMintVM::DOMAIN_SEPARATORCall::abi_decode_returns(&output, true)?._0, domain.separator());
+        insert_transaction(&mut conn, &renamed)?;
+
+        // Permits are tied to the contract they were signed for
+        let other = new_token(&mut conn, admin)?;
+        let mut foreign = permit(3, 1715136000);
+        let mut payload = payload::Permit::abi_decode(&foreign.data, true)?;
+        payload.token = other;
+        foreign.data = payload.abi_encode();
+        assert!(matches!(insert_transaction(&mut conn, &foreign), Err(DatabaseError::InvalidSignature(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: owner.0, id: ERC20_TOKEN_ID, amount: U256::from(1000) },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            spender,
+            TransactionType::TransferFrom,
+            payload::TransferFrom { token, from: owner.0, to: spender.0, amount: U256::from(500) },
+        ))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, spender, ERC20_TOKEN_ID)?, U256::from(500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clawback() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let operator = address(2);
+        let victim = address(3);
+        let recovery = address(4);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let sword = U256::from(7);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: victim.0, id: sword, amount: U256::from(1) },
+        ))?;
+        let claw_back = |sender: AddressSqlite| transaction(
+            sender,
+            TransactionType::ForceTransfer,
+            payload::ForceTransfer { token, from: victim.0, to: recovery.0, id: sword, amount: U256::from(1) },
+        );
+
+        // The role is not held by anyone until an admin grants it, not even
+        // by the creator
+        let result = insert_transaction(&mut conn, &claw_back(admin));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: operator.0, role: "Clawback".to_string() },
+        ))?;
+
+        let receipt = insert_transaction(&mut conn, &claw_back(operator))?;
+        assert!(receipt.clawback);
+        assert_eq!(Balances::balance_of(&conn, contract.id, victim, sword)?, U256::ZERO);
+        assert_eq!(Balances::balance_of(&conn, contract.id, recovery, sword)?, U256::from(1));
+        assert!(Receipts::get_by_transaction_id(&conn, receipt.transaction_id)?.clawback);
+
+        let events = Events::get_by_contract_id(&conn, contract.id)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].transaction_id, receipt.transaction_id);
+        assert_eq!(events[0].decode::<events::Clawback>()?, events::Clawback {
+            operator: operator.0,
+            from: victim.0,
+            to: recovery.0,
+            id: sword,
+            amount: U256::from(1),
+        });
+
+        // Regular transfers are not flagged
+        let receipt = insert_transaction(&mut conn, &transaction(
+            recovery,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: recovery.0, to: victim.0, id: sword, amount: U256::from(1) },
+        ))?;
+        assert!(!receipt.clawback);
+
+        // Only an admin can disable clawback, and only once
+        let disable = transaction(admin, TransactionType::DisableClawback, payload::DisableClawback { token });
+        let result = insert_transaction(&mut conn, &transaction(
+            operator,
+            TransactionType::DisableClawback,
+            payload::DisableClawback { token },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &disable)?;
+        assert!(Contracts::get_by_id(&conn, contract.id)?.clawback_disabled);
+        assert!(matches!(insert_transaction(&mut conn, &disable), Err(DatabaseError::ClawbackDisabled(_))));
+
+        let result = insert_transaction(&mut conn, &claw_back(operator));
+        assert!(matches!(result, Err(DatabaseError::ClawbackDisabled(_))));
+        assert_eq!(Balances::balance_of(&conn, contract.id, victim, sword)?, U256::from(1));
+
+        let events = Events::get_by_contract_id(&conn, contract.id)?;
+        assert_eq!(events.len(), 2);
+        events[1].decode::<events::ClawbackDisabled>()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clawback_through_proposals() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let (admin, operator, victim, recovery) = (address(1), address(2), address(3), address(4));
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let sword = U256::from(7);
+        insert_block(&conn, 1, 1715136000)?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: victim.0, id: sword, amount: U256::from(1) },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::GrantRole,
+            payload::GrantRole { token, account: operator.0, role: "Clawback".to_string() },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+
+        // Clawbacks now need a proposal, which only a holder of the role can
+        // make, however many signers approve it
+        let claw_back = payload::ForceTransfer {
+            token, from: victim.0, to: recovery.0, id: sword, amount: U256::from(1),
+        };
+        let propose = |proposer: AddressSqlite| transaction(
+            proposer,
+            TransactionType::ProposeOperation,
+            payload::ProposeOperation {
+                token,
+                transactionType: TransactionType::ForceTransfer.to_string(),
+                data: claw_back.abi_encode().into(),
+                expiresAt: 1715136000 + 3600,
+            },
+        );
+        let approve_and_execute = |conn: &mut Connection, approver: AddressSqlite, proposal_id: u64| {
+            insert_transaction(conn, &transaction(
+                approver,
+                TransactionType::ApproveProposal,
+                payload::ApproveProposal { token, proposalId: proposal_id },
+            ))?;
+            insert_transaction(conn, &transaction(
+                approver,
+                TransactionType::ExecuteProposal,
+                payload::ExecuteProposal { token, proposalId: proposal_id },
+            ))
+        };
+
+        insert_transaction(&mut conn, &propose(admin))?;
+        let result = approve_and_execute(&mut conn, operator, 1);
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        assert_eq!(Balances::balance_of(&conn, contract.id, victim, sword)?, U256::from(1));
+
+        insert_transaction(&mut conn, &propose(operator))?;
+        let receipt = approve_and_execute(&mut conn, admin, 2)?;
+        assert!(receipt.clawback);
+        assert_eq!(Balances::balance_of(&conn, contract.id, recovery, sword)?, U256::from(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocklist_and_recipient_allowlist() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let holder = address(2);
+        let blocked = address(3);
+        let listed = address(4);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let id = U256::from(1);
+        let mint = |to: AddressSqlite| transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: to.0, id, amount: U256::from(10) },
+        );
+        let send = |from: AddressSqlite, to: AddressSqlite| transaction(
+            from,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: from.0, to: to.0, id, amount: U256::from(1) },
+        );
+        let set_blocked = |account: AddressSqlite, blocked: bool| transaction(
+            admin,
+            TransactionType::SetBlocked,
+            payload::SetBlocked { token, account: account.0, blocked },
+        );
+
+        insert_transaction(&mut conn, &mint(holder))?;
+        insert_transaction(&mut conn, &mint(blocked))?;
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::SetBlocked,
+            payload::SetBlocked { token, account: blocked.0, blocked: true },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        let receipt = insert_transaction(&mut conn, &set_blocked(blocked, true))?;
+        assert_eq!(Contracts::get_by_blocked(&conn, blocked)?.len(), 1);
+        let events = Events::get_by_transaction_id(&conn, receipt.transaction_id)?;
+        assert_eq!(events[0].decode::<events::BlockedSet>()?, events::BlockedSet { account: blocked.0, blocked: true });
+
+        // Blocked addresses can neither send, receive nor be minted to
+        let result = insert_transaction(&mut conn, &send(blocked, holder));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+        let result = insert_transaction(&mut conn, &send(holder, blocked));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+        let result = insert_transaction(&mut conn, &mint(blocked));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+
+        // nor grant or receive approvals
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::Approve,
+            payload::Approve { token, owner: holder.0, spender: blocked.0, id },
+        ));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            blocked,
+            TransactionType::SetApprovalForAll,
+            payload::SetApprovalForAll { token, operator: holder.0, approved: true },
+        ));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            holder,
+            TransactionType::SetAllowance,
+            payload::SetAllowance { token, spender: blocked.0, amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+
+        insert_transaction(&mut conn, &set_blocked(blocked, false))?;
+        insert_transaction(&mut conn, &send(blocked, holder))?;
+        assert!(Contracts::get_by_blocked(&conn, blocked)?.is_empty());
+
+        // With the allowlist on, only listed addresses can receive
+        let receipt = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetAllowedRecipient,
+            payload::SetAllowedRecipient { token, account: listed.0, allowed: true },
+        ))?;
+        let events = Events::get_by_transaction_id(&conn, receipt.transaction_id)?;
+        assert_eq!(
+            events[0].decode::<events::AllowedRecipientSet>()?,
+            events::AllowedRecipientSet { account: listed.0, allowed: true }
+        );
+        insert_transaction(&mut conn, &send(holder, blocked))?;
+        let receipt = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetRecipientAllowlist,
+            payload::SetRecipientAllowlist { token, enabled: true },
+        ))?;
+        let events = Events::get_by_transaction_id(&conn, receipt.transaction_id)?;
+        assert_eq!(
+            events[0].decode::<events::RecipientAllowlistSet>()?,
+            events::RecipientAllowlistSet { enabled: true }
+        );
+        let result = insert_transaction(&mut conn, &send(holder, blocked));
+        assert!(matches!(result, Err(DatabaseError::RecipientNotAllowed(_))));
+        let result = insert_transaction(&mut conn, &mint(holder));
+        assert!(matches!(result, Err(DatabaseError::RecipientNotAllowed(_))));
+        insert_transaction(&mut conn, &send(holder, listed))?;
+        insert_transaction(&mut conn, &mint(listed))?;
+        assert_eq!(Balances::balance_of(&conn, contract.id, listed, id)?, U256::from(11));
+
+        // Unlisted holders can still send to listed addresses
+        insert_transaction(&mut conn, &send(blocked, listed))?;
+
+        // Blocking overrides the allowlist
+        insert_transaction(&mut conn, &set_blocked(listed, true))?;
+        let result = insert_transaction(&mut conn, &send(holder, listed));
+        assert!(matches!(result, Err(DatabaseError::AddressBlocked(_))));
+
+        // With a multisig, list changes need an approved proposal
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::AddTokenSigner,
+            payload::AddTokenSigner { token, signer: address(5).0 },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetApprovalThreshold,
+            payload::SetApprovalThreshold { token, threshold: 2 },
+        ))?;
+        let result = insert_transaction(&mut conn, &set_blocked(holder, true));
+        assert!(matches!(result, Err(DatabaseError::MultisigRequired(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetAllowedRecipient,
+            payload::SetAllowedRecipient { token, account: holder.0, allowed: true },
+        ));
+        assert!(matches!(result, Err(DatabaseError::MultisigRequired(_))));
+        let result = insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::SetRecipientAllowlist,
+            payload::SetRecipientAllowlist { token, enabled: false },
+        ));
+        assert!(matches!(result, Err(DatabaseError::MultisigRequired(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rentable_items() -> Result<(), Box<dyn std::error::Error>> {
+        use calls::IMintVM;
+
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let owner = address(2);
+        let renter = address(3);
+        let buyer = address(4);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let sword = U256::from(5);
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: owner.0, id: sword, amount: U256::from(1) },
+        ))?;
+        let set_user = |sender: AddressSqlite, expires: u64| transaction(
+            sender,
+            TransactionType::SetUser,
+            payload::SetUser { token, id: sword, user: renter.0, expires },
+        );
+        let call_user_of = |conn: &Connection| -> Result<Address, Box<dyn std::error::Error>> {
+            let output = eth_call(conn, token, &IMintVM::userOfCall { tokenId: sword }.abi_encode())?;
+            Ok(IMintVM::userOfCall::abi_decode_returns(&output, true)?._0)
+        };
+
+        let result = insert_transaction(&mut conn, &set_user(renter, 2000));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+        insert_transaction(&mut conn, &set_user(owner, 2000))?;
+        assert_eq!(TokenUsers::get_by_user(&conn, renter)?.len(), 1);
+        assert_uses_index(&conn, TokenUsers::SELECT_BY_USER, &["idx_token_users_user"]);
+        let events = Events::get_by_contract_id(&conn, contract.id)?;
+        assert_eq!(events[0].decode::<events::UpdateUser>()?, events::UpdateUser {
+            tokenId: sword,
+            user: renter.0,
+            expires: 2000,
+        });
+
+        // The role lasts until the block timestamp passes the expiry, and
+        // already holds at the genesis time before the first block
+        assert_eq!(call_user_of(&conn)?, renter.0);
+        insert_block(&conn, 1, 2000)?;
+        assert_eq!(call_user_of(&conn)?, renter.0);
+        assert_eq!(user_of(&conn, token, sword)?, renter.0);
+        insert_block(&conn, 2, 2001)?;
+        assert_eq!(call_user_of(&conn)?, Address::ZERO);
+        let output = eth_call(&conn, token, &IMintVM::userExpiresCall { tokenId: sword }.abi_encode())?;
+        assert_eq!(IMintVM::userExpiresCall::abi_decode_returns(&output, true)?._0, U256::from(2000));
+
+        // Renting does not hand over ownership
+        assert_eq!(contract.owner_of(&conn, sword)?, Some(owner));
+        let result = insert_transaction(&mut conn, &transaction(
+            renter,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: owner.0, to: renter.0, id: sword, amount: U256::from(1) },
+        ));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // A transfer clears the user
+        insert_transaction(&mut conn, &set_user(owner, 5000))?;
+        assert_eq!(call_user_of(&conn)?, renter.0);
+        let receipt = insert_transaction(&mut conn, &transaction(
+            owner,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: owner.0, to: buyer.0, id: sword, amount: U256::from(1) },
+        ))?;
+        assert_eq!(call_user_of(&conn)?, Address::ZERO);
+        assert!(TokenUsers::get(&conn, contract.id, sword)?.is_none());
+        let events = Events::get_by_transaction_id(&conn, receipt.transaction_id)?;
+        assert_eq!(events[0].decode::<events::UpdateUser>()?.user, Address::ZERO);
+
+        // type(uint64).max never expires
+        insert_transaction(&mut conn, &transaction(
+            buyer,
+            TransactionType::SetUser,
+            payload::SetUser { token, id: sword, user: renter.0, expires: u64::MAX },
+        ))?;
+        insert_block(&conn, 3, i64::MAX)?;
+        assert_eq!(call_user_of(&conn)?, renter.0);
+        let output = eth_call(&conn, token, &IMintVM::userExpiresCall { tokenId: sword }.abi_encode())?;
+        assert_eq!(IMintVM::userExpiresCall::abi_decode_returns(&output, true)?._0, U256::from(i64::MAX));
+
+        // Only ids held as a single item can be rented out
+        let potion = U256::from(6);
+        let mint = |to: AddressSqlite, amount: u64| transaction(
+            admin,
+            TransactionType::Mint,
+            payload::Mint { token, to: to.0, id: potion, amount: U256::from(amount) },
+        );
+        let rent_potion = |sender: AddressSqlite| transaction(
+            sender,
+            TransactionType::SetUser,
+            payload::SetUser { token, id: potion, user: renter.0, expires: u64::MAX },
+        );
+        let result = insert_transaction(&mut conn, &rent_potion(owner));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        insert_transaction(&mut conn, &mint(owner, 2))?;
+        let result = insert_transaction(&mut conn, &rent_potion(owner));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        insert_transaction(&mut conn, &transaction(
+            owner,
+            TransactionType::Transfer,
+            payload::Transfer { token, from: owner.0, to: buyer.0, id: potion, amount: U256::from(1) },
+        ))?;
+        let result = insert_transaction(&mut conn, &rent_potion(owner));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+
+        insert_transaction(&mut conn, &transaction(
+            admin,
+            TransactionType::MintRange,
+            payload::MintRange { token, to: owner.0, startId: U256::from(10), quantity: U256::from(3) },
+        ))?;
+        insert_transaction(&mut conn, &transaction(
+            owner,
+            TransactionType::SetUser,
+            payload::SetUser { token, id: U256::from(11), user: renter.0, expires: u64::MAX },
+        ))?;
+
+        let call = IMintVM::supportsInterfaceCall { interfaceId: INTERFACE_ID_ERC4907.into() };
+        let output = eth_call(&conn, token, &call.abi_encode())?;
+        assert!(IMintVM::supportsInterfaceCall::abi_decode_returns(&output, true)?._0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_attributes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = initialize_db()?;
+        let admin = address(1);
+        let player = address(2);
+        let token = new_token(&mut conn, admin)?;
+        let contract = Contracts::get_by_address(&conn, AddressSqlite::from(token))?;
+        let set_attributes = |sender: AddressSqlite, id: u64, attributes: &[(&str, &str)]| transaction(
+            sender,
+            TransactionType::SetTokenAttributes,
+            payload::SetTokenAttributes {
+                token,
+                id: U256::from(id),
+                attributes: attributes.iter()
+                    .map(|(key, value)| payload::TokenAttribute { key: key.to_string(), value: value.to_string() })
+                    .collect(),
+            },
+        );
+
+        let result = insert_transaction(&mut conn, &set_attributes(admin, 1, &[("level", "1")]));
+        assert!(matches!(result, Err(DatabaseError::InvalidTransactionData(_))));
+        let items = [(1, "sword", "12"), (2, "sword", "9"), (3, "sword", "10"), (4, "shield", "15"), (5, "sword", "max")];
+        for (id, kind, level) in items {
+            insert_transaction(&mut conn, &transaction(
+                admin,
+                TransactionType::Mint,
+                payload::Mint { token, to: player.0, id: U256::from(id), amount: U256::from(1) },
+            ))?;
+            insert_transaction(&mut conn, &set_attributes(admin, id, &[("kind", kind), ("level", level)]))?;
         }
 
+        // Holders cannot change their own items
+        let result = insert_transaction(&mut conn, &set_attributes(player, 2, &[("level", "99")]));
+        assert!(matches!(result, Err(DatabaseError::Unauthorized(_))));
+
+        // Values are kept as set, with a number only for numeric values
+        let level = TokenAttributes::get(&conn, contract.id, U256::from(1), "level")?.unwrap();
+        assert_eq!((level.value.as_str(), level.number), ("12", Some(12.0)));
+        let level = TokenAttributes::get(&conn, contract.id, U256::from(5), "level")?.unwrap();
+        assert_eq!((level.value.as_str(), level.number), ("max", None));
+        insert_transaction(&mut conn, &set_attributes(admin, 4, &[("serial", "007"), ("weight", "1e3")]))?;
+        let serial = TokenAttributes::get(&conn, contract.id, U256::from(4), "serial")?.unwrap();
+        assert_eq!((serial.value.as_str(), serial.number), ("007", Some(7.0)));
+        let weight = TokenAttributes::get(&conn, contract.id, U256::from(4), "weight")?.unwrap();
+        assert_eq!((weight.value.as_str(), weight.number), ("1e3", Some(1000.0)));
+
+        let leveled: Vec<U256> = TokenAttributes::get_by_key_at_least(&conn, contract.id, "level", 10.0)?
+            .into_iter()
+            .map(|attribute| attribute.token_id.0)
+            .collect();
+        assert_eq!(leveled, [U256::from(3), U256::from(1), U256::from(4)]);
+        assert_uses_index(&conn, TokenAttributes::SELECT_BY_KEY_AT_LEAST, &["idx_token_attributes_key_number"]);
+
+        // All swords with a level of at least 10
+        let mut stmt = conn.prepare(
+            "SELECT level.token_id FROM token_attributes AS level
+            JOIN token_attributes AS kind
+                ON kind.contract_id = level.contract_id AND kind.token_id = level.token_id AND kind.key = 'kind'
+            WHERE level.contract_id = ?1 AND level.key = 'level' AND level.number >= 10 AND kind.value = 'sword'
+            ORDER BY level.number",
+        )?;
+        let swords = stmt.query_map([contract.id], |row| row.get::<_, U256Sqlite>(0))?
+            .map(|id| id.map(|id| id.0))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(swords, [U256::from(3), U256::from(1)]);
+        drop(stmt);
+
+        // Each value is versioned with the transaction that set it
+        let receipt = insert_transaction(&mut conn, &set_attributes(admin, 2, &[("level", "10"), ("kind", "")]))?;
+        let attributes = TokenAttributes::get_by_token(&conn, contract.id, U256::from(2))?;
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(attributes[0].value, "10");
+        assert_eq!(attributes[0].transaction_id, receipt.transaction_id);
+
+        // Removing an attribute the token does not have is not recorded
+        insert_transaction(&mut conn, &set_attributes(admin, 2, &[("kind", "")]))?;
+
+        let history: Vec<_> = TokenAttributeHistory::get_by_token(&conn, contract.id, U256::from(2))?
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        assert_eq!(history, [
+            ("kind".to_string(), Some("sword".to_string())),
+            ("level".to_string(), Some("9".to_string())),
+            ("level".to_string(), Some("10".to_string())),
+            ("kind".to_string(), None),
+        ]);
+        assert_uses_index(&conn, TokenAttributeHistory::SELECT_BY_TOKEN, &["idx_token_attribute_history_token"]);
+
         Ok(())
     }
 }